@@ -0,0 +1,32 @@
+//! Compare the interpreter with the day 09 program compiled ahead of time:
+//!     cargo bench -p aoc-aot
+
+use aoc::intcode::{Machine, State};
+use common::{ms, time};
+
+#[path = "../../benches/common/mod.rs"]
+mod common;
+
+const ROUNDS: u32 = 20;
+
+fn main() {
+    let prog = aoc_aot::day09_prog();
+
+    let interpreted = time(ROUNDS, || {
+        let mut machine = Machine::new(&prog);
+        machine.push_input(2);
+        assert_eq!(machine.run(), Ok(State::Halt));
+        assert_eq!(machine.pop_output(), Some(87023));
+    });
+    let compiled = time(ROUNDS, || {
+        let mut machine = Machine::new(&prog);
+        machine.push_input(2);
+        assert_eq!(machine.run_compiled(aoc_aot::day09), Ok(State::Halt));
+        assert_eq!(machine.pop_output(), Some(87023));
+    });
+
+    println!("{:<14} {:>14}", "", "day09 part 2");
+    println!("{:<14} {:>11.3} ms", "interpreted", ms(interpreted));
+    println!("{:<14} {:>11.3} ms", "compiled", ms(compiled));
+    println!("{:<14} {:>13.1}x", "speedup", interpreted.as_secs_f64() / compiled.as_secs_f64());
+}
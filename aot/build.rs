@@ -0,0 +1,28 @@
+//! Translate the day 09 program and `selfmod.asm` to Rust, in `OUT_DIR`
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use itertools::Itertools;
+use aoc::intcode::{aot, asm};
+
+fn main() {
+    let out_dir = env::var("OUT_DIR").unwrap();
+    println!("cargo:rerun-if-changed=../input/day09.txt");
+    println!("cargo:rerun-if-changed=selfmod.asm");
+
+    let src = fs::read_to_string("../input/day09.txt").unwrap_or_else(|err| panic!("Can't read day09.txt: {}", err));
+    let prog = src.trim().split(',').map(|val| val.parse().expect("Invalid program")).collect_vec();
+    translate(&prog, "day09", &out_dir);
+
+    let src = fs::read_to_string("selfmod.asm").unwrap_or_else(|err| panic!("Can't read selfmod.asm: {}", err));
+    let prog = asm::assemble(&src).unwrap_or_else(|err| panic!("selfmod.asm:{}", err));
+    translate(&prog, "selfmod", &out_dir);
+}
+
+fn translate(prog: &[i64], name: &str, out_dir: &str) {
+    let src = aot::translate(prog, name).unwrap_or_else(|diags| {
+        panic!("Can't translate {}:\n{}", name, diags.iter().join("\n"))
+    });
+    fs::write(Path::new(out_dir).join(format!("{}.rs", name)), src).unwrap();
+}
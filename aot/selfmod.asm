@@ -0,0 +1,11 @@
+; Writes the first input value into data and the second one into the
+; cell of `OUT #0`, both through `rb`, then outputs both values
+
+        ARB #x
+        IN rb+0
+        ARB #out+1-x
+        IN rb+0
+out:    OUT #0
+        OUT [x]
+        HLT
+x:      DB 0
@@ -0,0 +1,66 @@
+//! Intcode programs translated ahead of time by `build.rs`, see
+//! `aoc::intcode::aot`: the day 09 program and `selfmod.asm`, which writes
+//! into its own code.
+
+use aoc::intcode::Memory;
+use aoc::intcode::aot::{Exit, Regs};
+
+include!(concat!(env!("OUT_DIR"), "/day09.rs"));
+include!(concat!(env!("OUT_DIR"), "/selfmod.rs"));
+
+/// Source of the `selfmod` program
+pub const SELFMOD: &str = include_str!("../selfmod.asm");
+
+/// The day 09 program that `day09` was translated from. It's embedded since
+/// the tests and the benchmark don't run from the directory of `input/`.
+pub fn day09_prog() -> Vec<i64> {
+    include_str!("../../input/day09.txt").trim().split(',').map(|val| val.parse().unwrap()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use itertools::Itertools;
+    use aoc::intcode::{Machine, State};
+    use aoc::intcode::asm::assemble;
+
+    #[test]
+    fn test_run_compiled() {
+        let prog = day09_prog();
+        for input in [1, 2] {
+            let mut interpreted = Machine::new(&prog);
+            interpreted.push_input(input);
+            assert_eq!(interpreted.run(), Ok(State::Halt));
+
+            // waits for input like the interpreter
+            let mut compiled = Machine::new(&prog);
+            assert_eq!(compiled.run_compiled(day09), Ok(State::WaitInput));
+            assert_eq!(compiled.ip(), 25);
+            compiled.push_input(input);
+            assert_eq!(compiled.run_compiled(day09), Ok(State::Halt));
+            assert_eq!(compiled.output(), interpreted.output());
+            assert_eq!(compiled.instr_count(), interpreted.instr_count());
+        }
+
+        // another program in memory is interpreted
+        let mut machine = Machine::new(&[104, 7, 99]);
+        assert_eq!(machine.run_compiled(day09), Ok(State::Halt));
+        assert_eq!(machine.pop_output(), Some(7));
+    }
+
+    #[test]
+    fn test_run_compiled_self_modifying() {
+        let prog = assemble(SELFMOD).unwrap();
+        let mut machine = Machine::new(&prog);
+        assert_eq!(machine.run_compiled(selfmod), Ok(State::WaitInput));
+        machine.push_input(8);
+        assert_eq!(machine.run_compiled(selfmod), Ok(State::WaitInput));
+        assert_eq!((machine.ip(), machine.peek(13)), (6, 8));
+
+        // the second IN writes into the code, so the interpreter runs it
+        machine.push_input(42);
+        assert_eq!(machine.run_compiled(selfmod), Ok(State::Halt));
+        assert_eq!(machine.drain_output().collect_vec(), [42, 8]);
+        assert_eq!(machine.instr_count(), 7);
+    }
+}
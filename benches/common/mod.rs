@@ -0,0 +1,24 @@
+//! Helpers shared by the benchmarks, included with `mod common;`
+
+// not every bench uses every helper
+#![allow(dead_code)]
+
+use std::time::{Duration, Instant};
+
+pub fn load_prog(day_xx: &str) -> Vec<i64> {
+    aoc::input::parse_tokens_split_str_unsafe::<i64>(day_xx, ",").next().unwrap()
+}
+
+/// Average time of `rounds` runs of `f`, after a warm-up run
+pub fn time(rounds: u32, f: impl Fn()) -> Duration {
+    f();
+    let start = Instant::now();
+    for _ in 0..rounds {
+        f();
+    }
+    start.elapsed() / rounds
+}
+
+pub fn ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
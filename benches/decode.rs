@@ -0,0 +1,31 @@
+//! Compare running with and without the decode cache:
+//!     cargo bench --bench decode
+
+use aoc::intcode::Machine;
+use common::{load_prog, ms, time};
+
+mod common;
+
+const ROUNDS: u32 = 20;
+
+fn main() {
+    let day09 = load_prog("day09");
+    let day05 = load_prog("day05");
+
+    println!("{:<14} {:>14} {:>14}", "", "day09 part 2", "day05 part 2");
+    for (name, cache) in [("cached", true), ("uncached", false)] {
+        let t09 = time(ROUNDS, || assert_eq!(run(&day09, 2, cache), 87023));
+        let t05 = time(ROUNDS, || assert_eq!(run(&day05, 5, cache), 3629692));
+        println!("{:<14} {:>11.3} ms {:>11.3} ms", name, ms(t09), ms(t05));
+    }
+}
+
+fn run(prog: &[i64], input: i64, cache: bool) -> i64 {
+    let mut machine = Machine::new(prog);
+    if !cache {
+        machine.set_decode_cache(0);
+    }
+    machine.push_input(input);
+    machine.run().unwrap();
+    machine.drain_output().last().unwrap()
+}
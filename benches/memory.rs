@@ -0,0 +1,61 @@
+//! Compare the memory backends running the day 09 and day 07 programs:
+//!     cargo bench --bench memory
+
+use itertools::Itertools;
+use aoc::intcode::{Machine, Memory, State};
+use aoc::intcode::memory::{DenseMemory, MapMemory, PagedMemory};
+use common::{load_prog, ms, time};
+
+mod common;
+
+const ROUNDS: u32 = 20;
+
+fn main() {
+    let day09 = load_prog("day09");
+    let day07 = load_prog("day07");
+
+    println!("{:<14} {:>14} {:>14}", "", "day09 part 2", "day07 part 2");
+    bench_memory::<DenseMemory>("DenseMemory", &day09, &day07);
+    bench_memory::<PagedMemory>("PagedMemory", &day09, &day07);
+    bench_memory::<MapMemory>("MapMemory", &day09, &day07);
+}
+
+fn bench_memory<M: Memory>(name: &str, day09: &[i64], day07: &[i64]) {
+    let t09 = time(ROUNDS, || assert_eq!(run_day09::<M>(day09), 87023));
+    let t07 = time(ROUNDS, || assert_eq!(run_day07::<M>(day07), 4039164));
+    println!("{:<14} {:>11.3} ms {:>11.3} ms", name, ms(t09), ms(t07));
+}
+
+fn run_day09<M: Memory>(prog: &[i64]) -> i64 {
+    let mut machine = Machine::<M>::with_memory(prog);
+    machine.push_input(2);
+    machine.run().unwrap();
+    machine.pop_output().unwrap()
+}
+
+/// Max signal of the amplifiers feedback loop over all phase permutations
+fn run_day07<M: Memory>(prog: &[i64]) -> i64 {
+    (5..10).permutations(5)
+        .map(|phases| {
+            let mut amps: Vec<Machine<M>> = phases.iter()
+                .map(|&phase| {
+                    let mut amp = Machine::with_memory(prog);
+                    amp.push_input(phase);
+                    amp
+                }).collect();
+
+            let mut signal = 0;
+            loop {
+                for (i, amp) in amps.iter_mut().enumerate() {
+                    amp.push_input(signal);
+                    let state = amp.run().unwrap();
+                    signal = amp.pop_output().unwrap();
+                    if i == 4 && state == State::Halt {
+                        return signal;
+                    }
+                }
+            }
+        })
+        .max()
+        .unwrap()
+}
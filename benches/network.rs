@@ -0,0 +1,42 @@
+//! Compare the round-robin and the threaded execution of networks, with the
+//! day 07 amplifiers feedback loop:
+//!     cargo bench --bench network
+
+use itertools::Itertools;
+use aoc::intcode::Machine;
+use aoc::intcode::network::{NetState, Network};
+use common::{load_prog, ms, time};
+
+mod common;
+
+const ROUNDS: u32 = 10;
+
+fn main() {
+    let prog = load_prog("day07");
+
+    println!("{:<14} {:>14}", "", "day07 part 2");
+    let t = time(ROUNDS, || assert_eq!(max_signal(&prog, Network::run), 4039164));
+    println!("{:<14} {:>11.3} ms", "round-robin", ms(t));
+    let t = time(ROUNDS, || assert_eq!(max_signal(&prog, Network::run_threaded), 4039164));
+    println!("{:<14} {:>11.3} ms", "threaded", ms(t));
+}
+
+fn max_signal<F, E>(prog: &[i64], run: F) -> i64
+where
+    F: Fn(&mut Network) -> Result<NetState, E>,
+    E: std::fmt::Debug,
+{
+    (5..10).permutations(5)
+        .map(|phases| {
+            let mut network = Network::ring(phases.iter().map(|&phase| {
+                let mut amp = Machine::new(prog);
+                amp.push_input(phase);
+                amp
+            }));
+            network.push_input(0, 0);
+            assert_eq!(run(&mut network).unwrap(), NetState::Halted);
+            network.machine(0).input()[0]
+        })
+        .max()
+        .unwrap()
+}
@@ -0,0 +1,38 @@
+use std::env;
+use std::fs;
+use std::process;
+use itertools::Itertools;
+use aoc::intcode::amp::CircuitConfig;
+
+/// Search the best phase settings of the amplifier circuits described in a
+/// config file (see `aoc::intcode::amp` for the format), running an Intcode
+/// program from the input dir, i.e.:
+///     cargo run --release --bin intamp day07 input/day07-circuits.txt
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (day_xx, config_path) = match args.as_slice() {
+        [day_xx, config_path] => (day_xx, config_path),
+        _ => {
+            eprintln!("Usage: intamp dayXX CONFIG");
+            process::exit(2);
+        },
+    };
+    let mut lines = aoc::input::parse_tokens_split_str_unsafe::<i64>(day_xx, ",");
+    let prog = lines.next().unwrap();
+
+    let config = fs::read_to_string(config_path).unwrap_or_else(|err| {
+        eprintln!("Can't read {}: {}", config_path, err);
+        process::exit(1);
+    });
+    let circuits = CircuitConfig::parse(&config).unwrap_or_else(|err| {
+        eprintln!("{}:{}", config_path, err);
+        process::exit(1);
+    });
+
+    for circuit in circuits {
+        match circuit.search(&prog) {
+            Some((phases, signal)) => println!("{}: signal = {}, phases = {}", circuit.name, signal, phases.iter().join(",")),
+            None => println!("{}: no phase settings produce a signal", circuit.name),
+        }
+    }
+}
@@ -0,0 +1,23 @@
+use std::env;
+use std::process;
+
+/// Translate an Intcode program from the input dir to the source of a Rust
+/// function called NAME, to run with `Machine::run_compiled`, i.e.:
+///     cargo run --bin intaot day09 day09 > day09.rs
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let [day_xx, name] = &args[..] else {
+        eprintln!("Usage: intaot dayXX NAME");
+        process::exit(2);
+    };
+    let mut lines = aoc::input::parse_tokens_split_str_unsafe::<i64>(day_xx, ",");
+    let prog = lines.next().unwrap();
+
+    match aoc::intcode::aot::translate(&prog, name) {
+        Ok(src) => print!("{}", src),
+        Err(diags) => {
+            diags.iter().for_each(|diag| eprintln!("{}", diag));
+            process::exit(1);
+        },
+    }
+}
@@ -0,0 +1,93 @@
+use std::collections::VecDeque;
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process;
+use aoc::intcode::{IntcodeInput, IntcodeOutput, Machine, State};
+
+/// Run an Intcode program from the input dir that talks ASCII: each line of
+/// input is sent as its character codes followed by a newline, and outputs
+/// are printed as characters, or as numbers if they're not ASCII. With
+/// --script, the lines of a transcript file are sent first, and then the
+/// input continues from stdin. I.e.:
+///     cargo run --bin intascii day25 [--script day25.txt]
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (day_xx, script) = match args.as_slice() {
+        [day_xx] => (day_xx, None),
+        [day_xx, flag, path] if flag == "--script" => (day_xx, Some(path)),
+        _ => {
+            eprintln!("Usage: intascii dayXX [--script FILE]");
+            process::exit(2);
+        },
+    };
+    let mut lines = aoc::input::parse_tokens_split_str_unsafe::<i64>(day_xx, ",");
+    let prog = lines.next().unwrap();
+
+    let script = script.map(|path| {
+        let file = File::open(path).unwrap_or_else(|err| panic!("Can't open {}: {}", path, err));
+        BufReader::new(file).lines()
+    });
+
+    let mut input = AsciiInput { script, pending: VecDeque::new() };
+    let mut machine = Machine::new(&prog);
+    match machine.run_io(&mut input, &mut AsciiOutput) {
+        Ok(State::Halt) => (),
+        Ok(_) => println!("\n[input closed while the program was waiting for more]"),
+        Err(err) => {
+            eprintln!("\nFault: {}", err);
+            process::exit(1);
+        },
+    }
+}
+
+struct AsciiInput {
+    script: Option<io::Lines<BufReader<File>>>,
+    pending: VecDeque<i64>,
+}
+
+impl AsciiInput {
+    /// Next line of the script, echoed so the transcript can be followed, or
+    /// from stdin once the script is over
+    fn next_line(&mut self) -> Option<String> {
+        io::stdout().flush().ok()?;
+        if let Some(script) = self.script.as_mut() {
+            match script.next() {
+                Some(line) => {
+                    let line = line.expect("Error reading the script");
+                    println!("{}", line);
+                    return Some(line);
+                },
+                None => self.script = None,
+            }
+        }
+
+        let mut line = String::new();
+        match io::stdin().lock().read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(line.trim_end_matches(['\n', '\r']).to_string()),
+        }
+    }
+}
+
+impl IntcodeInput for AsciiInput {
+    fn read(&mut self) -> Option<i64> {
+        if self.pending.is_empty() {
+            let line = self.next_line()?;
+            self.pending.extend(line.bytes().map(i64::from));
+            self.pending.push_back(b'\n' as i64);
+        }
+        self.pending.pop_front()
+    }
+}
+
+struct AsciiOutput;
+
+impl IntcodeOutput for AsciiOutput {
+    fn write(&mut self, val: i64) {
+        match u8::try_from(val) {
+            Ok(c) if c.is_ascii() => print!("{}", c as char),
+            _ => println!("{}", val),
+        }
+    }
+}
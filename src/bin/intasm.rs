@@ -0,0 +1,17 @@
+use std::{env, fs, process};
+use aoc::intcode::asm;
+
+/// Assemble an Intcode program and print it in the input files format, i.e.:
+///     cargo run --bin intasm prog.asm > input/dayXX.txt
+fn main() {
+    let path = env::args().nth(1).expect("Usage: intasm FILE");
+    let src = fs::read_to_string(&path).expect("Can't read file");
+
+    match asm::assemble(&src) {
+        Ok(prog) => println!("{}", asm::to_text(&prog)),
+        Err(err) => {
+            eprintln!("{}:{}", path, err);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,19 @@
+use std::env;
+use std::process;
+
+/// Check an Intcode program from the input dir without running it, printing
+/// the errors and warnings found. Exits with an error if there's any error:
+///     cargo run --bin intcheck day09
+fn main() {
+    let day_xx = env::args().nth(1).expect("Usage: intcheck dayXX");
+    let mut lines = aoc::input::parse_tokens_split_str_unsafe::<i64>(&day_xx, ",");
+    let prog = lines.next().unwrap();
+
+    let diags = aoc::intcode::analyze(&prog);
+    for diag in &diags {
+        println!("{}", diag);
+    }
+    if diags.iter().any(|diag| diag.is_error()) {
+        process::exit(1);
+    }
+}
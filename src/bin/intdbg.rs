@@ -0,0 +1,379 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use itertools::Itertools;
+use aoc::intcode::{Instr, IntcodeError, Machine, Step, UndoLog};
+
+const HELP: &str = "\
+Commands:
+  s, step [N]              execute N instructions (default 1)
+  c, continue              run until a breakpoint, watchpoint, halt or fault
+  rs, step-back [N]        undo the last N executed instructions (default 1)
+  rc, reverse-continue     undo instructions until a breakpoint, the previous
+                           change of a watched address or the start of history
+  b, break ADDR            set a breakpoint at ADDR
+  d, delete ADDR           remove the breakpoint at ADDR
+  w, watch ADDR            stop when the value at ADDR changes
+  unwatch ADDR             remove the watchpoint at ADDR
+  i, info                  list breakpoints and watchpoints
+  r, regs                  print ip, rel_base and the I/O queues
+  x, mem ADDR [LEN]        dump LEN memory cells from ADDR (default 16)
+  poke ADDR VAL            write VAL at ADDR
+  jump ADDR                move ip to ADDR
+  l, dis [N]               disassemble N instructions around ip (default 10)
+  in [VAL...]              show the input queue, or push values to it
+  in clear                 clear the input queue
+  out [clear]              show the output queue, or clear it
+  save FILE                save a snapshot of the machine state to FILE
+  load FILE                restore the machine state from a snapshot in FILE
+  h, help                  show this help
+  q, quit                  exit
+An empty line repeats the last command. History for stepping back is lost
+after changing the machine with poke, jump or load.";
+
+/// Max number of executed instructions that can be undone
+const HISTORY_LEN: usize = 1_000_000;
+
+/// Interactive debugger for Intcode programs from the input dir, i.e.:
+///     cargo run --bin intdbg day09 [INPUT...]
+fn main() {
+    let mut args = env::args().skip(1);
+    let day_xx = args.next().expect("Usage: intdbg dayXX [INPUT...]");
+    let mut lines = aoc::input::parse_tokens_split_str_unsafe::<i64>(&day_xx, ",");
+    let prog = lines.next().unwrap();
+
+    let mut dbg = Debugger::new(Machine::new(&prog));
+    dbg.machine.extend_input(args.map(|arg| arg.parse::<i64>().expect("Invalid input value")));
+
+    println!("Loaded {} ({} cells). Type 'help' for the list of commands.", day_xx, prog.len());
+    dbg.print_current();
+
+    let stdin = io::stdin();
+    let mut last_cmd = String::new();
+    loop {
+        print!("(intdbg) ");
+        io::stdout().flush().unwrap();
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap() == 0 {
+            break;
+        }
+        if line.trim().is_empty() {
+            line = last_cmd.clone();
+        } else {
+            last_cmd = line.clone();
+        }
+
+        let cmd: Vec<&str> = line.split_whitespace().collect();
+        match dbg.exec(&cmd) {
+            Ok(true) => (),
+            Ok(false) => break,
+            Err(msg) => println!("{}", msg),
+        }
+    }
+}
+
+struct Debugger {
+    machine: Machine,
+    breakpoints: BTreeSet<usize>,
+    /// Watched addresses with the last value seen
+    watchpoints: BTreeMap<usize, i64>,
+}
+
+/// Why execution stopped
+enum Stop {
+    Steps,
+    Breakpoint(usize),
+    Watchpoint(usize, i64, i64),
+    Halt,
+    WaitInput,
+    Fault(IntcodeError),
+    /// Nothing left to undo
+    HistoryStart,
+}
+
+impl Debugger {
+    fn new(mut machine: Machine) -> Debugger {
+        machine.set_tracer(UndoLog::new(HISTORY_LEN));
+        Debugger {
+            machine,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeMap::new(),
+        }
+    }
+
+    /// Execute a command, returning false if the debugger must exit
+    fn exec(&mut self, cmd: &[&str]) -> Result<bool, String> {
+        let (&name, args) = match cmd.split_first() {
+            Some(split) => split,
+            None => return Ok(true),
+        };
+
+        match name {
+            "s" | "step" => {
+                let n = parse_arg(args.first(), 1)?;
+                let stop = self.exec_steps(Some(n));
+                self.print_stop(stop);
+            },
+            "c" | "continue" => {
+                let stop = self.exec_steps(None);
+                self.print_stop(stop);
+            },
+            "rs" | "step-back" => {
+                let n = parse_arg(args.first(), 1)?;
+                let stop = self.undo_steps(Some(n));
+                self.print_stop(stop);
+            },
+            "rc" | "reverse-continue" => {
+                let stop = self.undo_steps(None);
+                self.print_stop(stop);
+            },
+            "b" | "break" => {
+                self.breakpoints.insert(parse_arg(args.first(), None)?);
+            },
+            "d" | "delete" => {
+                let addr = parse_arg(args.first(), None)?;
+                if !self.breakpoints.remove(&addr) {
+                    return Err(format!("No breakpoint at {}", addr));
+                }
+            },
+            "w" | "watch" => {
+                let addr = parse_arg(args.first(), None)?;
+                self.watchpoints.insert(addr, self.machine.peek(addr));
+            },
+            "unwatch" => {
+                let addr = parse_arg(args.first(), None)?;
+                if self.watchpoints.remove(&addr).is_none() {
+                    return Err(format!("No watchpoint at {}", addr));
+                }
+            },
+            "i" | "info" => {
+                println!("Breakpoints: {}", self.breakpoints.iter().join(", "));
+                println!("Watchpoints: {}", self.watchpoints.keys().join(", "));
+                println!("History: {} instructions", self.history().len());
+            },
+            "r" | "regs" => {
+                println!("ip = {}, rel_base = {}, halted = {}",
+                         self.machine.ip(), self.machine.rel_base(), self.machine.is_halted());
+                println!("input:  [{}]", self.machine.input().iter().join(", "));
+                println!("output: [{}]", self.machine.output().iter().join(", "));
+            },
+            "x" | "mem" => {
+                let addr = parse_arg(args.first(), None)?;
+                let len = parse_arg(args.get(1), 16)?;
+                self.print_mem(addr, len)?;
+            },
+            "poke" => {
+                let addr = parse_arg(args.first(), None)?;
+                let val = parse_arg(args.get(1), None)?;
+                self.machine.poke(addr, val);
+                self.history().clear();
+            },
+            "jump" => {
+                let addr = parse_arg(args.first(), None)?;
+                self.machine.set_ip(addr).map_err(|_| format!("Invalid address {}", addr))?;
+                self.history().clear();
+                self.print_current();
+            },
+            "l" | "dis" => {
+                let n = parse_arg(args.first(), 10)?;
+                self.print_disasm(n);
+            },
+            "in" => match args {
+                [] => println!("input: [{}]", self.machine.input().iter().join(", ")),
+                ["clear"] => self.machine.input_mut().clear(),
+                _ => {
+                    let vals = args.iter()
+                        .map(|arg| parse_arg(Some(arg), None))
+                        .collect::<Result<Vec<i64>, _>>()?;
+                    self.machine.extend_input(vals);
+                },
+            },
+            "out" => match args {
+                [] => println!("output: [{}]", self.machine.output().iter().join(", ")),
+                ["clear"] => self.machine.output_mut().clear(),
+                _ => return Err("Usage: out [clear]".to_string()),
+            },
+            "save" => {
+                let path = args.first().ok_or("Usage: save FILE")?;
+                let file = File::create(path).map_err(|err| err.to_string())?;
+                self.machine.save_snapshot(BufWriter::new(file)).map_err(|err| err.to_string())?;
+            },
+            "load" => {
+                let path = args.first().ok_or("Usage: load FILE")?;
+                let file = File::open(path).map_err(|err| err.to_string())?;
+                self.machine = Machine::load_snapshot(BufReader::new(file)).map_err(|err| err.to_string())?;
+                self.machine.set_tracer(UndoLog::new(HISTORY_LEN));
+                for (&addr, last_val) in self.watchpoints.iter_mut() {
+                    *last_val = self.machine.peek(addr);
+                }
+                self.print_current();
+            },
+            "h" | "help" => println!("{}", HELP),
+            "q" | "quit" => return Ok(false),
+            _ => return Err(format!("Unknown command '{}', type 'help'", name)),
+        }
+
+        Ok(true)
+    }
+
+    /// Execute `n` instructions, or until something stops the execution
+    /// if `n` is None
+    fn exec_steps(&mut self, n: Option<usize>) -> Stop {
+        let mut count = 0;
+        loop {
+            if n == Some(count) {
+                return Stop::Steps;
+            }
+
+            match self.machine.step() {
+                Ok(Step::Exec(_)) if self.machine.is_halted() => return Stop::Halt,
+                Ok(Step::Exec(_)) => (),
+                Ok(Step::Halted) => return Stop::Halt,
+                Ok(Step::WaitInput) => return Stop::WaitInput,
+                Err(err) => return Stop::Fault(err),
+            }
+            count += 1;
+
+            if let Some(stop) = self.check_watchpoints() {
+                return stop;
+            }
+
+            let ip = self.machine.ip() as usize;
+            if self.breakpoints.contains(&ip) {
+                return Stop::Breakpoint(ip);
+            }
+        }
+    }
+
+    /// Undo `n` instructions, or until a breakpoint or a change of a watched
+    /// address if `n` is None. Watchpoints work like in `exec_steps`, so a
+    /// watchpoint stops before the instruction that changed the value, with
+    /// the value after it as the old one.
+    fn undo_steps(&mut self, n: Option<usize>) -> Stop {
+        let mut count = 0;
+        loop {
+            if n == Some(count) {
+                return Stop::Steps;
+            }
+
+            let Some(record) = self.history().pop() else {
+                return Stop::HistoryStart;
+            };
+            self.machine.undo(&record);
+            count += 1;
+
+            if let Some(stop) = self.check_watchpoints() {
+                return stop;
+            }
+
+            let ip = self.machine.ip() as usize;
+            if self.breakpoints.contains(&ip) {
+                return Stop::Breakpoint(ip);
+            }
+        }
+    }
+
+    /// Stop at the first watched address whose value is not the last one
+    /// seen, i.e. changed by an instruction or a poke
+    fn check_watchpoints(&mut self) -> Option<Stop> {
+        for (&addr, last_val) in self.watchpoints.iter_mut() {
+            let val = self.machine.peek(addr);
+            if val != *last_val {
+                let stop = Stop::Watchpoint(addr, *last_val, val);
+                *last_val = val;
+                return Some(stop);
+            }
+        }
+        None
+    }
+
+    fn history(&mut self) -> &mut UndoLog {
+        self.machine.tracer_mut::<UndoLog>().unwrap()
+    }
+
+    fn print_stop(&self, stop: Stop) {
+        match stop {
+            Stop::Steps => (),
+            Stop::Breakpoint(addr) => println!("Breakpoint at {}", addr),
+            Stop::Watchpoint(addr, old, new) => println!("Watchpoint at {}: {} -> {}", addr, old, new),
+            Stop::Halt => println!("Program halted"),
+            Stop::WaitInput => println!("Waiting for input, push it with 'in VAL'"),
+            Stop::Fault(err) => println!("Fault: {}", err),
+            Stop::HistoryStart => println!("Start of the history, nothing else to undo"),
+        }
+        self.print_current();
+    }
+
+    fn print_current(&self) {
+        let ip = self.machine.ip() as usize;
+        println!("{}", self.format_instr(ip).1);
+    }
+
+    fn print_mem(&self, addr: usize, len: usize) -> Result<(), String> {
+        let end = addr.checked_add(len).ok_or_else(|| format!("Invalid range of {} cells from {}", len, addr))?;
+        for row_addr in (addr..end).step_by(8) {
+            let row_end = row_addr.saturating_add(8).min(end);
+            let vals = (row_addr..row_end).map(|a| self.machine.peek(a)).join(" ");
+            println!("{:>6}: {}", row_addr, vals);
+        }
+        Ok(())
+    }
+
+    /// Disassemble `n` instructions around ip. Instructions don't have a
+    /// fixed size, so the ones before ip are guessed decoding from the
+    /// furthest address that leads exactly to ip.
+    fn print_disasm(&self, n: usize) {
+        let ip = self.machine.ip() as usize;
+        let before = n / 2;
+        let prev_addrs = (ip.saturating_sub(before.saturating_mul(4))..ip)
+            .find_map(|start| self.decode_until(start, ip))
+            .unwrap_or_default();
+        let skip = prev_addrs.len().saturating_sub(before);
+        let mut addr = prev_addrs.get(skip).copied().unwrap_or(ip);
+
+        for _ in 0..n {
+            let (size, line) = self.format_instr(addr);
+            let marker = if addr == ip { "=>" } else if self.breakpoints.contains(&addr) { " *" } else { "  " };
+            println!("{} {}", marker, line);
+            let Some(next) = addr.checked_add(size) else {
+                break;
+            };
+            addr = next;
+        }
+    }
+
+    /// Addresses of the instructions decoded from `addr`, if they end
+    /// exactly at `target`
+    fn decode_until(&self, mut addr: usize, target: usize) -> Option<Vec<usize>> {
+        let mut addrs = Vec::new();
+        while addr < target {
+            addrs.push(addr);
+            addr = addr.checked_add(Instr::decode(addr, |a| self.machine.peek(a)).ok()?.size())?;
+        }
+        (addr == target).then_some(addrs)
+    }
+
+    /// Format the instruction at `addr`, or the raw value if it's not a valid
+    /// instruction, returning also its size
+    fn format_instr(&self, addr: usize) -> (usize, String) {
+        match Instr::decode(addr, |a| self.machine.peek(a)) {
+            Ok(instr) => {
+                let raw = (addr..addr.saturating_add(instr.size())).map(|a| self.machine.peek(a)).join(",");
+                (instr.size(), format!("{:>6}  {:<32}{}", addr, raw, instr))
+            },
+            Err(_) => (1, format!("{:>6}  {:<32}DB {}", addr, "", self.machine.peek(addr))),
+        }
+    }
+}
+
+/// Parse an optional argument, falling back to `default` if missing. If the
+/// argument is mandatory, pass `None` as default.
+fn parse_arg<T: std::str::FromStr>(arg: Option<&&str>, default: impl Into<Option<T>>) -> Result<T, String> {
+    match (arg, default.into()) {
+        (Some(arg), _) => arg.parse().map_err(|_| format!("Invalid argument '{}'", arg)),
+        (None, Some(default)) => Ok(default),
+        (None, None) => Err("Missing argument".to_string()),
+    }
+}
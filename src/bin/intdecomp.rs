@@ -0,0 +1,11 @@
+use std::env;
+
+/// Print an Intcode program from the input dir lifted to pseudo-code, i.e.:
+///     cargo run --bin intdecomp day09
+fn main() {
+    let day_xx = env::args().nth(1).expect("Usage: intdecomp dayXX");
+    let mut lines = aoc::input::parse_tokens_split_str_unsafe::<i64>(&day_xx, ",");
+    let prog = lines.next().unwrap();
+
+    print!("{}", aoc::intcode::decompile(&prog));
+}
@@ -0,0 +1,11 @@
+use std::env;
+
+/// Print the disassembly of an Intcode program from the input dir, i.e.:
+///     cargo run --bin intdisasm day09
+fn main() {
+    let day_xx = env::args().nth(1).expect("Usage: intdisasm dayXX");
+    let mut lines = aoc::input::parse_tokens_split_str_unsafe::<i64>(&day_xx, ",");
+    let prog = lines.next().unwrap();
+
+    print!("{}", aoc::intcode::disasm(&prog));
+}
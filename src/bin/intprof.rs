@@ -0,0 +1,33 @@
+use std::env;
+use aoc::intcode::Machine;
+use aoc::intcode::profile::Profiler;
+
+/// Number of hot instructions and loops listed in the report
+const TOP: usize = 20;
+
+/// Profile an Intcode program from the input dir, printing a report or, with
+/// --folded, folded stacks to feed to a flamegraph tool. I.e.:
+///     cargo run --release --bin intprof day09 2
+///     cargo run --release --bin intprof day09 --folded 2 | flamegraph.pl > day09.svg
+fn main() {
+    let mut args = env::args().skip(1).peekable();
+    let day_xx = args.next().expect("Usage: intprof dayXX [--folded] [INPUT...]");
+    let folded = args.next_if(|arg| arg == "--folded").is_some();
+    let mut lines = aoc::input::parse_tokens_split_str_unsafe::<i64>(&day_xx, ",");
+    let prog = lines.next().unwrap();
+
+    let mut machine = Machine::new(&prog);
+    machine.extend_input(args.map(|arg| arg.parse::<i64>().expect("Invalid input value")));
+    machine.set_tracer(Profiler::new());
+
+    let result = machine.run();
+    let profiler = machine.take_tracer::<Profiler>().unwrap();
+
+    if folded {
+        print!("{}", profiler.folded());
+    } else {
+        println!("Result: {:?}", result);
+        println!("Output: {:?}\n", machine.drain_output().collect::<Vec<_>>());
+        print!("{}", profiler.report(TOP));
+    }
+}
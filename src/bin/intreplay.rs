@@ -0,0 +1,102 @@
+use std::env;
+use std::fs;
+use std::io::{self, BufReader};
+use std::process;
+use aoc::intcode::Machine;
+use aoc::intcode::amp::{self, CircuitConfig};
+use aoc::intcode::replay::{self, Session};
+
+const USAGE: &str = "Usage: intreplay record dayXX [INPUTS...]
+       intreplay record dayXX --circuits CONFIG
+       intreplay check dayXX REPLAY_FILE";
+
+/// Record the I/O of an Intcode program from the input dir to a replay file
+/// (see `aoc::intcode::replay` for the format), or check that the program
+/// still behaves like a replay file says. INPUTS are comma separated input
+/// values, one session per argument; with a circuits config, a session is
+/// recorded per amplifier of the best phase settings of every circuit, i.e.:
+///     cargo run --bin intreplay record day05 1 5 > src/intcode/replay/day05.txt
+///     cargo run --bin intreplay check day05 src/intcode/replay/day05.txt
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (cmd, day_xx, rest) = match args.as_slice() {
+        [cmd, day_xx, rest @ ..] => (cmd.as_str(), day_xx, rest),
+        _ => usage(),
+    };
+    let mut lines = aoc::input::parse_tokens_split_str_unsafe::<i64>(day_xx, ",");
+    let prog = lines.next().unwrap();
+
+    match (cmd, rest) {
+        ("record", [flag, config_path]) if flag == "--circuits" => {
+            let sessions = record_circuits(&prog, config_path);
+            replay::write(&sessions, io::stdout().lock()).unwrap();
+        },
+        ("record", inputs) => {
+            let sessions: Vec<Session> = inputs.iter().map(|inputs| {
+                let mut machine = Machine::new(&prog);
+                machine.extend_input(parse_inputs(inputs));
+                replay::record(&mut machine)
+            }).collect();
+            replay::write(&sessions, io::stdout().lock()).unwrap();
+        },
+        ("check", [path]) => check(&prog, path),
+        _ => usage(),
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+fn parse_inputs(inputs: &str) -> Vec<i64> {
+    inputs.split(',').filter(|val| !val.is_empty()).map(|val| {
+        val.trim().parse().unwrap_or_else(|_| {
+            eprintln!("Invalid input value: {}", val);
+            process::exit(2);
+        })
+    }).collect()
+}
+
+fn record_circuits(prog: &[i64], config_path: &str) -> Vec<Session> {
+    let config = fs::read_to_string(config_path).unwrap_or_else(|err| {
+        eprintln!("Can't read {}: {}", config_path, err);
+        process::exit(1);
+    });
+    let circuits = CircuitConfig::parse(&config).unwrap_or_else(|err| {
+        eprintln!("{}:{}", config_path, err);
+        process::exit(1);
+    });
+
+    let mut sessions = Vec::new();
+    for circuit in circuits {
+        let Some((phases, _)) = circuit.search(prog) else {
+            eprintln!("{}: no phase settings produce a signal", circuit.name);
+            process::exit(1);
+        };
+        let mut network = amp::build_circuit(prog, &phases, circuit.topology, circuit.initial_signal).unwrap();
+        sessions.extend(replay::record_network(&mut network));
+    }
+    sessions
+}
+
+fn check(prog: &[i64], path: &str) {
+    let sessions = fs::File::open(path)
+        .and_then(|file| replay::read(BufReader::new(file)))
+        .unwrap_or_else(|err| {
+            eprintln!("Can't read {}: {}", path, err);
+            process::exit(1);
+        });
+
+    let mut failed = false;
+    for (i, session) in sessions.iter().enumerate() {
+        if let Err(err) = session.replay(prog) {
+            println!("session {}: {}", i + 1, err);
+            failed = true;
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+    println!("{} sessions replayed", sessions.len());
+}
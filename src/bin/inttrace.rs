@@ -0,0 +1,41 @@
+use std::env;
+use std::fs::File;
+use std::io::BufWriter;
+use aoc::intcode::Machine;
+use aoc::intcode::trace::{BinaryTracer, TextTracer};
+
+/// Run an Intcode program from the input dir and write a trace of every
+/// executed instruction: as text if the trace file ends with .txt, or in the
+/// binary format otherwise. I.e.:
+///     cargo run --bin inttrace day09 day09.trace 1
+fn main() {
+    let mut args = env::args().skip(1);
+    let (day_xx, trace_path) = match (args.next(), args.next()) {
+        (Some(day_xx), Some(trace_path)) => (day_xx, trace_path),
+        _ => panic!("Usage: inttrace dayXX TRACE_FILE [INPUT...]"),
+    };
+    let mut lines = aoc::input::parse_tokens_split_str_unsafe::<i64>(&day_xx, ",");
+    let prog = lines.next().unwrap();
+
+    let mut machine = Machine::new(&prog);
+    machine.extend_input(args.map(|arg| arg.parse::<i64>().expect("Invalid input value")));
+
+    let writer = BufWriter::new(File::create(&trace_path).expect("Can't create trace file"));
+    let text = trace_path.ends_with(".txt");
+    if text {
+        machine.set_tracer(TextTracer::new(writer));
+    } else {
+        machine.set_tracer(BinaryTracer::new(writer));
+    }
+
+    let result = machine.run();
+
+    let written = match text {
+        true => machine.take_tracer::<TextTracer<BufWriter<File>>>().unwrap().into_inner().map(|_| ()),
+        false => machine.take_tracer::<BinaryTracer<BufWriter<File>>>().unwrap().into_inner().map(|_| ()),
+    };
+    written.expect("Error writing trace");
+
+    println!("Result: {:?} after {} instructions", result, machine.instr_count());
+    println!("Output: {:?}", machine.drain_output().collect::<Vec<_>>());
+}
@@ -0,0 +1,123 @@
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::process;
+use aoc::intcode::trace::{TraceReader, TraceRecord};
+
+/// Compare two Intcode traces written by `inttrace` or by a `Tracer`, and
+/// report the first record where they diverge. Both traces must be in the
+/// same format, binary or text. I.e.:
+///     cargo run --bin tracediff good.trace bad.trace
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() != 2 {
+        eprintln!("Usage: tracediff TRACE_A TRACE_B");
+        process::exit(2);
+    }
+
+    let result = match (is_binary(&args[0]), is_binary(&args[1])) {
+        (Ok(true), Ok(true)) => diff_binary(&args[0], &args[1]),
+        (Ok(false), Ok(false)) => diff_text(&args[0], &args[1]),
+        (Err(err), _) | (_, Err(err)) => Err(err),
+        _ => Err(io::Error::other("can't compare a binary trace with a text trace")),
+    };
+
+    match result {
+        Ok(true) => println!("Traces are identical"),
+        Ok(false) => process::exit(1),
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            process::exit(2);
+        }
+    }
+}
+
+fn is_binary(path: &str) -> io::Result<bool> {
+    let mut magic = [0; 4];
+    let n = File::open(path)?.read(&mut magic)?;
+    Ok(n == 4 && &magic == b"ICTR")
+}
+
+/// Compare record by record, returning true if the traces are equal
+fn diff_binary(path_a: &str, path_b: &str) -> io::Result<bool> {
+    let mut trace_a = TraceReader::new(BufReader::new(File::open(path_a)?))?;
+    let mut trace_b = TraceReader::new(BufReader::new(File::open(path_b)?))?;
+
+    for i in 0.. {
+        match (trace_a.next().transpose()?, trace_b.next().transpose()?) {
+            (None, None) => break,
+            (Some(a), Some(b)) if a == b => (),
+            (Some(a), Some(b)) => {
+                println!("Traces diverge at record {}: {}", i, divergence(&a, &b));
+                println!("< {}", a);
+                println!("> {}", b);
+                return Ok(false);
+            },
+            (a, b) => {
+                print_end(i, a.as_ref().map(|r| r.to_string()), b.as_ref().map(|r| r.to_string()));
+                return Ok(false);
+            },
+        }
+    }
+
+    Ok(true)
+}
+
+/// Compare line by line, returning true if the traces are equal
+fn diff_text(path_a: &str, path_b: &str) -> io::Result<bool> {
+    let mut trace_a = BufReader::new(File::open(path_a)?).lines();
+    let mut trace_b = BufReader::new(File::open(path_b)?).lines();
+
+    for i in 0.. {
+        match (trace_a.next().transpose()?, trace_b.next().transpose()?) {
+            (None, None) => break,
+            (Some(a), Some(b)) if a == b => (),
+            (Some(a), Some(b)) => {
+                println!("Traces diverge at line {}", i + 1);
+                println!("< {}", a);
+                println!("> {}", b);
+                return Ok(false);
+            },
+            (a, b) => {
+                print_end(i, a, b);
+                return Ok(false);
+            },
+        }
+    }
+
+    Ok(true)
+}
+
+fn print_end(i: usize, a: Option<String>, b: Option<String>) {
+    match (a, b) {
+        (Some(a), None) => println!("Trace B ends after {} records, trace A continues with:\n< {}", i, a),
+        (None, Some(b)) => println!("Trace A ends after {} records, trace B continues with:\n> {}", i, b),
+        _ => unreachable!(),
+    }
+}
+
+/// Describe the first field that differs between two records
+fn divergence(a: &TraceRecord, b: &TraceRecord) -> String {
+    if a.ip != b.ip {
+        return format!("ip {} vs {}", a.ip, b.ip);
+    }
+    if a.rel_base != b.rel_base {
+        return format!("rel_base {} vs {}", a.rel_base, b.rel_base);
+    }
+    if a.instr != b.instr {
+        return format!("instruction {} vs {}", a.instr, b.instr);
+    }
+    let addr = |addr: Option<usize>| addr.map_or("?".to_string(), |addr| addr.to_string());
+    for (i, (op_a, op_b)) in a.operands().iter().zip(b.operands()).enumerate() {
+        if op_a.addr != op_b.addr {
+            return format!("operand {} address {} vs {}", i + 1, addr(op_a.addr), addr(op_b.addr));
+        }
+        if op_a.pre != op_b.pre {
+            return format!("operand {} value before {} vs {}", i + 1, op_a.pre, op_b.pre);
+        }
+        if op_a.post != op_b.post {
+            return format!("operand {} value after {} vs {}", i + 1, op_a.post, op_b.post);
+        }
+    }
+    format!("instruction count {} vs {}", a.count, b.count)
+}
@@ -0,0 +1,317 @@
+//! Amplifier circuits like the ones of day 07: copies of the same program,
+//! each one initialized with a phase setting, connected in a chain or in a
+//! feedback loop, and a parallel search of the phase settings that produce
+//! the highest output signal.
+//!
+//! Circuits can be described in a config file, with a section per circuit:
+//!
+//! ```text
+//! # comments start with a hash
+//! [Part 2]
+//! amplifiers = 5          # optional, as many as phases by default
+//! topology = feedback     # chain (default) or feedback
+//! phases = 5..=9          # a..b, a..=b or a comma separated list
+//! signal = 0              # initial input signal, 0 by default
+//! ```
+//!
+//! A circuit can have up to 16 phase values, and up to a million phase
+//! settings to search, since every one of them runs the whole circuit.
+
+use std::error::Error;
+use std::fmt;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use itertools::Itertools;
+use super::Machine;
+use super::network::{NetState, Network};
+
+/// Max number of phase values of a circuit, so also of amplifiers
+const MAX_PHASES: usize = 16;
+/// Max number of phase settings that `CircuitConfig::search` can try
+const MAX_PERMUTATIONS: u64 = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Topology {
+    /// Each amplifier outputs to the next one, the last one outputs the signal
+    Chain,
+    /// Like `Chain`, but the last amplifier outputs to the first one, until
+    /// all of them halt
+    Feedback,
+}
+
+/// A circuit from a config file, with the phase values to search
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CircuitConfig {
+    pub name: String,
+    pub amplifiers: usize,
+    pub topology: Topology,
+    pub phases: Vec<i64>,
+    pub initial_signal: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    /// Line of the config where the error was found, starting at 1
+    pub line: usize,
+    pub msg: String,
+}
+
+impl CircuitConfig {
+    /// Parse all the circuits of a config file
+    pub fn parse(src: &str) -> Result<Vec<CircuitConfig>, ConfigError> {
+        let mut circuits = Vec::new();
+        // circuit being parsed, with the line of its header and its amplifiers if given
+        let mut current: Option<(usize, CircuitConfig, Option<usize>)> = None;
+
+        for (i, line) in src.lines().enumerate() {
+            let err = |msg: String| ConfigError { line: i + 1, msg };
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                if let Some(circuit) = current.take() {
+                    circuits.push(finish_circuit(circuit)?);
+                }
+                let circuit = CircuitConfig {
+                    name: name.trim().to_string(),
+                    amplifiers: 0,
+                    topology: Topology::Chain,
+                    phases: Vec::new(),
+                    initial_signal: 0,
+                };
+                current = Some((i + 1, circuit, None));
+                continue;
+            }
+
+            let (_, circuit, amplifiers) = current.as_mut()
+                .ok_or_else(|| err("expected a [circuit name] section first".to_string()))?;
+            let (key, val) = line.split_once('=')
+                .ok_or_else(|| err(format!("expected 'key = value', found '{}'", line)))?;
+            let (key, val) = (key.trim(), val.trim());
+            let parse_int = |s: &str| s.trim().parse::<i64>().map_err(|_| err(format!("invalid number '{}'", s.trim())));
+
+            match key {
+                "amplifiers" => {
+                    let n = usize::try_from(parse_int(val)?).ok().filter(|&n| n > 0)
+                        .ok_or_else(|| err(format!("invalid number of amplifiers '{}'", val)))?;
+                    if n > MAX_PHASES {
+                        return Err(err(format!("too many amplifiers, the max is {}", MAX_PHASES)));
+                    }
+                    *amplifiers = Some(n);
+                },
+                "topology" => {
+                    circuit.topology = match val {
+                        "chain" => Topology::Chain,
+                        "feedback" => Topology::Feedback,
+                        _ => return Err(err(format!("unknown topology '{}'", val))),
+                    };
+                },
+                "phases" => {
+                    // one more than the max to tell that a range is too long
+                    circuit.phases = match (val.split_once("..="), val.split_once("..")) {
+                        (Some((a, b)), _) => (parse_int(a)?..=parse_int(b)?).take(MAX_PHASES + 1).collect(),
+                        (None, Some((a, b))) => (parse_int(a)?..parse_int(b)?).take(MAX_PHASES + 1).collect(),
+                        (None, None) => val.split(',').map(parse_int).collect::<Result<_, _>>()?,
+                    };
+                    if circuit.phases.len() > MAX_PHASES {
+                        return Err(err(format!("too many phase values, the max is {}", MAX_PHASES)));
+                    }
+                    if !circuit.phases.iter().all_unique() {
+                        return Err(err("duplicated phase values".to_string()));
+                    }
+                },
+                "signal" => circuit.initial_signal = parse_int(val)?,
+                _ => return Err(err(format!("unknown key '{}'", key))),
+            }
+        }
+
+        if let Some(circuit) = current {
+            circuits.push(finish_circuit(circuit)?);
+        }
+        Ok(circuits)
+    }
+
+    /// Search the phase settings that give the highest signal with `prog`
+    pub fn search(&self, prog: &[i64]) -> Option<(Vec<i64>, i64)> {
+        let eval = |phases: &[i64]| run_circuit(prog, phases, self.topology, self.initial_signal);
+        best_permutation(&self.phases, self.amplifiers, eval)
+    }
+}
+
+fn finish_circuit((line, mut circuit, amplifiers): (usize, CircuitConfig, Option<usize>))
+        -> Result<CircuitConfig, ConfigError> {
+    let err = |msg: String| ConfigError { line, msg };
+    if circuit.phases.is_empty() {
+        return Err(err(format!("circuit '{}' has no phases", circuit.name)));
+    }
+    circuit.amplifiers = amplifiers.unwrap_or(circuit.phases.len());
+    if circuit.amplifiers > circuit.phases.len() {
+        return Err(err(format!("circuit '{}' has more amplifiers than phase values", circuit.name)));
+    }
+    // phases.len() * (phases.len() - 1) * ... for each amplifier
+    let n = circuit.phases.len() as u64;
+    let permutations = (0..circuit.amplifiers as u64).try_fold(1u64, |acc, i| acc.checked_mul(n - i));
+    if permutations.is_none_or(|count| count > MAX_PERMUTATIONS) {
+        return Err(err(format!("circuit '{}' has more than {} phase settings to search", circuit.name, MAX_PERMUTATIONS)));
+    }
+    Ok(circuit)
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.msg)
+    }
+}
+
+impl Error for ConfigError {}
+
+/// Run a circuit with an amplifier per phase setting, returning the output
+/// signal, or `None` if the circuit doesn't produce a single output value
+pub fn run_circuit(prog: &[i64], phases: &[i64], topology: Topology, initial_signal: i64) -> Option<i64> {
+    let mut network = build_circuit(prog, phases, topology, initial_signal)?;
+    if network.run().ok()? != NetState::Halted {
+        return None;
+    }
+    let signal = match topology {
+        Topology::Chain => network.machine(network.len() - 1).output(),
+        Topology::Feedback => network.machine(0).input(),
+    };
+    match signal.len() {
+        1 => signal.front().copied(),
+        _ => None,
+    }
+}
+
+/// Network of amplifiers for a circuit, ready to run with the initial signal
+/// as input, or `None` if there are no phase settings
+pub fn build_circuit(prog: &[i64], phases: &[i64], topology: Topology, initial_signal: i64) -> Option<Network> {
+    let amps = phases.iter().map(|&phase| {
+        let mut amp = Machine::new(prog);
+        amp.push_input(phase);
+        amp
+    });
+    let mut network = match topology {
+        Topology::Chain => Network::chain(amps),
+        Topology::Feedback => Network::ring(amps),
+    };
+    if network.is_empty() {
+        return None;
+    }
+    network.push_input(0, initial_signal);
+    Some(network)
+}
+
+/// Evaluate all the permutations of `n` values from `domain` in parallel,
+/// returning the one with the highest value and that value. If there's a
+/// tie, the first permutation in lexicographic order of `domain` indexes wins.
+/// Permutations evaluated as `None` are skipped.
+pub fn best_permutation<F>(domain: &[i64], n: usize, eval: F) -> Option<(Vec<i64>, i64)>
+where
+    F: Fn(&[i64]) -> Option<i64> + Sync,
+{
+    if n == 0 || n > domain.len() {
+        return None;
+    }
+
+    // every task is the permutations starting with a given value
+    let next_task = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::new());
+    let n_threads = thread::available_parallelism().map_or(1, |n| n.get()).min(domain.len());
+
+    thread::scope(|scope| {
+        for _ in 0..n_threads {
+            scope.spawn(|| loop {
+                let first = next_task.fetch_add(1, Ordering::Relaxed);
+                if first >= domain.len() {
+                    return;
+                }
+                let rest = (0..domain.len()).filter(|&i| i != first);
+                let best = rest.permutations(n - 1)
+                    .filter_map(|mut idxs| {
+                        idxs.insert(0, first);
+                        let perm = idxs.iter().map(|&i| domain[i]).collect_vec();
+                        eval(&perm).map(|val| (val, idxs, perm))
+                    })
+                    .max_by(|(a, a_idxs, _), (b, b_idxs, _)| a.cmp(b).then(b_idxs.cmp(a_idxs)));
+                results.lock().unwrap().extend(best);
+            });
+        }
+    });
+
+    results.into_inner().unwrap().into_iter()
+        .max_by(|(a, a_idxs, _), (b, b_idxs, _)| a.cmp(b).then(b_idxs.cmp(a_idxs)))
+        .map(|(val, _, perm)| (perm, val))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_best_permutation() {
+        // value is the number formed by the digits, if the first one is odd
+        let eval = |perm: &[i64]| match perm[0] % 2 {
+            0 => None,
+            _ => Some(perm.iter().fold(0, |acc, d| acc * 10 + d)),
+        };
+        assert_eq!(best_permutation(&[1, 2, 3, 4], 3, eval), Some((vec![3, 4, 2], 342)));
+        assert_eq!(best_permutation(&[2, 4], 2, eval), None);
+        assert_eq!(best_permutation(&[1, 2], 3, eval), None);
+
+        // ties are won by the first permutation
+        assert_eq!(best_permutation(&[5, 6, 7], 2, |_| Some(0)), Some((vec![5, 6], 0)));
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let config = "
+            # example
+            [Part 1]
+            phases = 0..5
+            [Part 2]
+            amplifiers = 3   # only 3 of them
+            topology = feedback
+            phases = 5, 7, 9, 11
+            signal = -1
+        ";
+        let circuits = CircuitConfig::parse(config).unwrap();
+        assert_eq!(circuits, [
+            CircuitConfig { name: "Part 1".into(), amplifiers: 5, topology: Topology::Chain, phases: vec![0, 1, 2, 3, 4], initial_signal: 0 },
+            CircuitConfig { name: "Part 2".into(), amplifiers: 3, topology: Topology::Feedback, phases: vec![5, 7, 9, 11], initial_signal: -1 },
+        ]);
+
+        let err = |src| CircuitConfig::parse(src).unwrap_err().to_string();
+        assert_eq!(err("phases = 1"), "line 1: expected a [circuit name] section first");
+        assert_eq!(err("[a]\nphases = 1..=3\ntopology = star"), "line 3: unknown topology 'star'");
+        assert_eq!(err("[a]\nphases = 1, x"), "line 2: invalid number 'x'");
+        assert_eq!(err("[a]\nphases = 1, 1"), "line 2: duplicated phase values");
+        assert_eq!(err("[a]\n[b]\nphases = 1"), "line 1: circuit 'a' has no phases");
+        assert_eq!(err("[a]\namplifiers = 3\nphases = 1, 2"), "line 1: circuit 'a' has more amplifiers than phase values");
+
+        // limits
+        assert_eq!(err("[a]\nphases = 0..=16"), "line 2: too many phase values, the max is 16");
+        assert_eq!(err("[a]\nphases = 0..9223372036854775807"), "line 2: too many phase values, the max is 16");
+        assert_eq!(err("[a]\namplifiers = 17"), "line 2: too many amplifiers, the max is 16");
+        assert_eq!(err("[a]\nphases = 0..10"), "line 1: circuit 'a' has more than 1000000 phase settings to search");
+        assert_eq!(CircuitConfig::parse("[a]\nphases = 0..9").map(|c| c[0].amplifiers), Ok(9));
+        assert_eq!(CircuitConfig::parse("[a]\namplifiers = 5\nphases = 0..16").map(|c| c[0].amplifiers), Ok(5));
+    }
+
+    #[test]
+    fn test_run_circuit() {
+        // example programs from day 07
+        let prog = [3, 15, 3, 16, 1002, 16, 10, 16, 1, 16, 15, 15, 4, 15, 99, 0, 0];
+        assert_eq!(run_circuit(&prog, &[4, 3, 2, 1, 0], Topology::Chain, 0), Some(43210));
+        assert_eq!(best_permutation(&[0, 1, 2, 3, 4], 5, |p| run_circuit(&prog, p, Topology::Chain, 0)),
+                   Some((vec![4, 3, 2, 1, 0], 43210)));
+
+        let prog = [
+            3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26,
+            27, 4, 27, 1001, 28, -1, 28, 1005, 28, 6, 99, 0, 0, 5,
+        ];
+        assert_eq!(run_circuit(&prog, &[9, 8, 7, 6, 5], Topology::Feedback, 0), Some(139629729));
+    }
+}
@@ -0,0 +1,312 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use super::{Instr, IntcodeError, Mode, Opcode};
+
+/// Something wrong, or suspicious, found by `analyze`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Diagnostic {
+    /// A reachable instruction that will fault if it's executed
+    Error(IntcodeError),
+    /// The reachable instruction at `ip` writes to `addr`, which is part of
+    /// the instruction at `target`
+    SelfModifying { ip: usize, addr: usize, target: usize },
+    /// The cells `start..end` decode as a block of instructions ending in a
+    /// HLT or a jump, but no reachable instruction leads to them
+    Unreachable { start: usize, end: usize },
+}
+
+/// Control flow graph of a program, as far as it can be followed statically
+pub(super) struct Cfg {
+    /// Reachable instructions by address
+    pub code: BTreeMap<usize, Instr>,
+    /// Addresses that are jump targets
+    pub targets: BTreeSet<usize>,
+    /// Instructions in `code` that are surely reachable, not only through a
+    /// guessed return address
+    pub certain: BTreeSet<usize>,
+    /// Surely reachable addresses that don't hold a valid instruction
+    pub invalid: BTreeMap<usize, IntcodeError>,
+    /// Whether there are jumps to computed addresses, which can't be
+    /// followed, so some code may be reachable although it's not in `code`
+    pub indirect: bool,
+}
+
+impl Diagnostic {
+    /// Address of the instruction or cells that the diagnostic is about
+    pub fn addr(&self) -> usize {
+        match *self {
+            Diagnostic::Error(err) => err.ip() as usize,
+            Diagnostic::SelfModifying { ip, .. } => ip,
+            Diagnostic::Unreachable { start, .. } => start,
+        }
+    }
+
+    /// Whether the program will surely fail, instead of just being suspicious
+    pub fn is_error(&self) -> bool {
+        matches!(self, Diagnostic::Error(_))
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Diagnostic::Error(err) => write!(f, "error: {}", err),
+            Diagnostic::SelfModifying { ip, addr, target } => {
+                write!(f, "warning: instruction at {} writes to {}, inside the instruction at {}", ip, addr, target)
+            },
+            Diagnostic::Unreachable { start, end } => {
+                write!(f, "warning: unreachable code at {}..{}", start, end)
+            },
+        }
+    }
+}
+
+/// Check a program before running it, following the control flow from
+/// address 0. Returns the diagnostics sorted by address.
+///
+/// Reachable instructions are checked for invalid opcodes and modes, writes
+/// to immediate parameters, negative addresses, and writes into the cells of
+/// reachable instructions. Faults are only reported for the code that is
+/// surely reachable, not for the code after a guessed return address (see
+/// `disasm`), and invalid instructions are not errors if the program may
+/// patch them first, like day 05 does. Writes and jumps in relative mode
+/// can't be checked. Unreachable code is only reported if the program has no
+/// jumps to computed addresses (i.e. no function returns), otherwise there's
+/// no way to tell.
+pub fn analyze(prog: &[i64]) -> Vec<Diagnostic> {
+    let cfg = Cfg::build(prog);
+    let mut diags = Vec::new();
+    let mut written = BTreeSet::new();
+
+    for (&ip, instr) in &cfg.code {
+        let fault = |param: usize| -> Option<IntcodeError> {
+            let p = instr.params()[param];
+            let writes = instr.opcode.dest_param() == Some(param);
+            let jumps = param == 1 && matches!(instr.opcode, Opcode::Jt | Opcode::Jf);
+            let (ip, instr) = (ip as i64, instr.word);
+            match p.mode {
+                Mode::Imm if writes => Some(IntcodeError::WriteToImmediate { ip, instr, param: param + 1 }),
+                Mode::Imm if jumps && p.value < 0 => Some(IntcodeError::NegativeAddress { ip, instr, addr: p.value }),
+                Mode::Pos if p.value < 0 => Some(IntcodeError::NegativeAddress { ip, instr, addr: p.value }),
+                _ => None,
+            }
+        };
+        if cfg.certain.contains(&ip) {
+            diags.extend((0..instr.params().len()).filter_map(fault).map(Diagnostic::Error));
+        }
+
+        let dest = instr.opcode.dest_param().map(|i| instr.params()[i]);
+        if let Some(dest) = dest.filter(|p| p.mode == Mode::Pos && p.value >= 0) {
+            let addr = dest.value as usize;
+            let target = cfg.instr_at(addr).or(cfg.invalid.contains_key(&addr).then_some(addr));
+            if let Some(target) = target {
+                diags.push(Diagnostic::SelfModifying { ip, addr, target });
+                written.insert(addr);
+            }
+        }
+    }
+
+    // an invalid instruction may be fixed by the program before reaching it
+    diags.extend(cfg.invalid.iter()
+        .filter(|(addr, _)| !written.contains(*addr))
+        .map(|(_, &err)| Diagnostic::Error(err)));
+
+    if !cfg.indirect {
+        diags.extend(unreachable_code(prog, &cfg));
+    }
+
+    diags.sort_by_key(|diag| diag.addr());
+    diags
+}
+
+/// Runs of cells outside the reachable code that decode as instructions up
+/// to a HLT or a jump, like a block of code would
+fn unreachable_code<'a>(prog: &'a [i64], cfg: &'a Cfg) -> impl Iterator<Item = Diagnostic> + 'a {
+    let read = |addr: usize| prog.get(addr).copied().unwrap_or(0);
+
+    let mut gaps = Vec::new();
+    let mut addr = 0;
+    while addr < prog.len() {
+        let start = addr;
+        while addr < prog.len() && cfg.instr_at(addr).is_none() {
+            addr += 1;
+        }
+        if addr > start {
+            gaps.push((start, addr));
+        }
+        addr += cfg.code.get(&addr).map_or(1, |instr| instr.size());
+    }
+
+    // only the start of a gap is checked, what's after the block is usually data
+    gaps.into_iter().filter_map(move |(start, end)| {
+        let mut addr = start;
+        while addr < end {
+            let instr = Instr::decode(addr, read).ok()?;
+            addr += instr.size();
+            if matches!(instr.opcode, Opcode::Hlt | Opcode::Jt | Opcode::Jf) {
+                return (addr <= end).then_some(Diagnostic::Unreachable { start, end: addr });
+            }
+        }
+        None
+    })
+}
+
+impl Cfg {
+    /// Follow the control flow from address 0
+    pub fn build(prog: &[i64]) -> Cfg {
+        let read = |addr: usize| prog.get(addr).copied().unwrap_or(0);
+        let mut cfg = Cfg {
+            code: BTreeMap::new(),
+            targets: BTreeSet::new(),
+            certain: BTreeSet::new(),
+            invalid: BTreeMap::new(),
+            indirect: false,
+        };
+        // addresses to visit, and whether they come from a guessed return address
+        let mut pending = vec![(0, false)];
+
+        while let Some((addr, guessed)) = pending.pop() {
+            let visited = match guessed {
+                true => cfg.code.contains_key(&addr),
+                false => cfg.certain.contains(&addr),
+            };
+            if visited || cfg.invalid.contains_key(&addr) {
+                continue;
+            }
+            let instr = match Instr::decode(addr, read) {
+                Ok(instr) => instr,
+                Err(err) => {
+                    if !guessed {
+                        cfg.invalid.insert(addr, err);
+                    }
+                    continue;
+                },
+            };
+            cfg.code.insert(addr, instr);
+            if !guessed {
+                cfg.certain.insert(addr);
+            }
+
+            let next = addr + instr.size();
+            match instr.opcode {
+                Opcode::Hlt => (),
+                Opcode::Jt | Opcode::Jf => {
+                    let (taken, not_taken) = branch_outcomes(&instr);
+                    match (taken, imm_target(&instr)) {
+                        (true, Some(target)) => {
+                            cfg.targets.insert(target);
+                            pending.push((target, guessed));
+                        },
+                        (true, None) => cfg.indirect |= instr.params()[1].mode != Mode::Imm,
+                        (false, _) => (),
+                    }
+                    if not_taken {
+                        pending.push((next, guessed));
+                    }
+                },
+                Opcode::Add | Opcode::Mul => {
+                    if let Some(ret_addr) = return_addr(&instr, next, read) {
+                        cfg.targets.insert(ret_addr);
+                        pending.push((ret_addr, true));
+                    }
+                    pending.push((next, guessed));
+                },
+                _ => pending.push((next, guessed)),
+            }
+        }
+
+        cfg
+    }
+
+    /// Address of the reachable instruction that `addr` is part of
+    pub fn instr_at(&self, addr: usize) -> Option<usize> {
+        let (&start, instr) = self.code.range(..=addr).next_back()?;
+        (addr < start + instr.size()).then_some(start)
+    }
+}
+
+/// Whether a conditional jump can be taken and whether it can fall through,
+/// as far as it can be told from an immediate condition
+pub(super) fn branch_outcomes(instr: &Instr) -> (bool, bool) {
+    let cond = instr.params()[0];
+    if cond.mode != Mode::Imm {
+        return (true, true);
+    }
+    let jumps = (cond.value != 0) == (instr.opcode == Opcode::Jt);
+    (jumps, !jumps)
+}
+
+pub(super) fn imm_target(instr: &Instr) -> Option<usize> {
+    match instr.params()[1] {
+        p if p.mode == Mode::Imm && p.value >= 0 => Some(p.value as usize),
+        _ => None,
+    }
+}
+
+/// If `instr` stores a constant and is followed by an unconditional jump, the
+/// constant is probably the return address of a call
+fn return_addr(instr: &Instr, next: usize, read: impl Fn(usize) -> i64) -> Option<usize> {
+    let params = instr.params();
+    if params[0].mode != Mode::Imm || params[1].mode != Mode::Imm {
+        return None;
+    }
+
+    let next_instr = Instr::decode(next, &read).ok()?;
+    let is_jump = matches!(next_instr.opcode, Opcode::Jt | Opcode::Jf);
+    if !is_jump || branch_outcomes(&next_instr) != (true, false) {
+        return None;
+    }
+
+    let val = match instr.opcode {
+        Opcode::Add => params[0].value.checked_add(params[1].value)?,
+        _ => params[0].value.checked_mul(params[1].value)?,
+    };
+    usize::try_from(val).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_analyze() {
+        let prog = [
+            3, 13,          // IN [13]
+            1005, 13, 9,    // JT [13], #9
+            1101, 1, 1, 6,  // ADD #1, #1, [6]  writes into itself
+            99,             // HLT
+            104, 0, 99,     // OUT #0; HLT      unreachable
+            0,
+        ];
+        let diags = analyze(&prog);
+        assert_eq!(diags, [
+            Diagnostic::SelfModifying { ip: 5, addr: 6, target: 5 },
+            Diagnostic::Unreachable { start: 10, end: 13 },
+        ]);
+        assert!(!diags.iter().any(Diagnostic::is_error));
+        assert_eq!(diags[1].to_string(), "warning: unreachable code at 10..13");
+
+        let prog = [
+            1005, 7, 9,     // JT [7], #9
+            11101, 1, 1, 7, // ADD #1, #1, #7
+            99, 0,
+            1, -1, 0, 0,    // ADD [-1], [0], [0]
+            55,             // unknown opcode
+        ];
+        let diags = analyze(&prog);
+        assert_eq!(diags, [
+            Diagnostic::Error(IntcodeError::WriteToImmediate { ip: 3, instr: 11101, param: 3 }),
+            Diagnostic::Error(IntcodeError::NegativeAddress { ip: 9, instr: 1, addr: -1 }),
+            Diagnostic::SelfModifying { ip: 9, addr: 0, target: 0 },
+            Diagnostic::Error(IntcodeError::UnknownOpcode { ip: 13, instr: 55, opcode: 55 }),
+        ]);
+
+        // the return address after the call can't be followed, so nothing is unreachable
+        let prog = [21101, 7, 0, 1, 1105, 1, 8, 99, 2106, 0, 1, 104, 0, 99];
+        assert_eq!(analyze(&prog), []);
+
+        // a wrongly guessed return address isn't an error
+        let prog = [1101, 7, 0, 20, 1105, 1, 8, 55, 99];
+        assert_eq!(analyze(&prog), []);
+    }
+}
@@ -0,0 +1,309 @@
+//! Ahead-of-time translation of Intcode programs to Rust source, to compile
+//! them natively instead of interpreting them.
+//!
+//! `translate` generates a function with a `match ip` dispatch loop, with an
+//! arm per basic block, that runs on the registers and memory of a `Machine`
+//! lent by `Machine::run_compiled`. Anything the generated code doesn't
+//! handle makes it stop before executing the instruction and return
+//! `Exit::Fallback`, so the interpreter takes over from there with the same
+//! state: faults, jumps to addresses that are not the start of a block, and
+//! writes into the code, since the translation would be stale after them.
+//!
+//! The generated source uses `Exit`, `Memory` and `Regs` unqualified, so it
+//! can be written by a build script and `include!`d where they are in scope:
+//!
+//! ```ignore
+//! mod day09 {
+//!     use aoc::intcode::Memory;
+//!     use aoc::intcode::aot::{Exit, Regs};
+//!     include!(concat!(env!("OUT_DIR"), "/day09.rs"));
+//! }
+//! ```
+//!
+//! The `aoc-aot` crate in `aot/` does that with day 09, for its tests and
+//! benchmark.
+
+use std::collections::{BTreeSet, VecDeque};
+use std::fmt::Write;
+use itertools::Itertools;
+use super::analyze::{branch_outcomes, imm_target, Cfg};
+use super::{analyze, Diagnostic, IntcodeError, Machine, Memory, Mode, Opcode, State};
+
+const INDENT: &str = "    ";
+
+/// Registers and memory of a machine, as seen by the generated code
+pub struct Regs<'a, M> {
+    pub mem: &'a mut M,
+    pub ip: i64,
+    pub rel_base: i64,
+    pub input: &'a mut VecDeque<i64>,
+    pub output: &'a mut VecDeque<i64>,
+    pub instr_count: u64,
+}
+
+/// Why the generated code returned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exit {
+    Halt,
+    WaitInput,
+    /// The instruction at `ip` must be run by the interpreter
+    Fallback,
+}
+
+impl<M: Memory> Regs<'_, M> {
+    /// Address `rel_base + offset`, if it's valid
+    #[inline]
+    pub fn rel(&self, offset: i64) -> Option<usize> {
+        usize::try_from(self.rel_base.checked_add(offset)?).ok()
+    }
+
+    /// Stop at the instruction at `ip`, after executing `executed`
+    /// instructions since the count was last updated
+    #[inline]
+    pub fn fallback(&mut self, ip: i64, executed: u64) -> Exit {
+        self.ip = ip;
+        self.instr_count += executed;
+        Exit::Fallback
+    }
+
+    /// Whether the memory holds `code`, runs of cells by start address
+    pub fn code_matches(&self, code: &[(usize, &[i64])]) -> bool {
+        code.iter().all(|&(start, vals)| {
+            vals.iter().enumerate().all(|(i, &val)| self.mem.get(start + i) == val)
+        })
+    }
+}
+
+impl<M: Memory> Machine<M> {
+    /// Like `run`, but with `compiled`, the function generated by `translate`
+    /// for the loaded program, falling back to the interpreter wherever the
+    /// generated code can't go on. If the code in memory is not the one that
+    /// was translated, or there's a tracer, only the interpreter is used. So
+    /// is it with `Arithmetic::Big`, which the generated code doesn't handle.
+    pub fn run_compiled(&mut self, compiled: impl FnOnce(&mut Regs<M>) -> Exit) -> Result<State, IntcodeError> {
+        if self.halted || self.tracer.is_some() {
+            return self.run();
+        }
+        #[cfg(feature = "bignum")]
+        if self.arithmetic == super::Arithmetic::Big {
+            return self.run();
+        }
+
+        let mut regs = Regs {
+            mem: &mut self.mem,
+            ip: self.ip,
+            rel_base: self.rel_base,
+            input: &mut self.input,
+            output: &mut self.output,
+            instr_count: self.instr_count,
+        };
+        let exit = compiled(&mut regs);
+        (self.ip, self.rel_base, self.instr_count) = (regs.ip, regs.rel_base, regs.instr_count);
+        // the generated code writes to memory without invalidating the cache
+        self.set_decode_cache(self.decode_cache_len);
+
+        match exit {
+            Exit::Halt => {
+                self.halted = true;
+                Ok(State::Halt)
+            },
+            Exit::WaitInput => Ok(State::WaitInput),
+            Exit::Fallback => self.run(),
+        }
+    }
+}
+
+/// Translate `prog` into the source of a Rust function called `name`, to be
+/// run with `Machine::run_compiled`.
+///
+/// The program is checked with `analyze` first: if it has errors or writes
+/// into its own code, those diagnostics are returned instead.
+pub fn translate(prog: &[i64], name: &str) -> Result<String, Vec<Diagnostic>> {
+    let diags = analyze(prog).into_iter()
+        .filter(|diag| matches!(diag, Diagnostic::Error(_) | Diagnostic::SelfModifying { .. }))
+        .collect_vec();
+    if !diags.is_empty() {
+        return Err(diags);
+    }
+
+    let cfg = Cfg::build(prog);
+    let mut runs: Vec<(usize, usize)> = Vec::new();
+    for (&addr, instr) in &cfg.code {
+        match runs.last_mut() {
+            Some((_, end)) if *end >= addr => *end = (*end).max(addr + instr.size()),
+            _ => runs.push((addr, addr + instr.size())),
+        }
+    }
+
+    // blocks start at jump targets and after jumps, and at IN instructions
+    // so the code can be resumed after waiting for input
+    let mut leaders: BTreeSet<usize> = cfg.targets.iter().copied().chain([0]).collect();
+    for (&addr, instr) in &cfg.code {
+        match instr.opcode {
+            Opcode::In => leaders.insert(addr),
+            Opcode::Jt | Opcode::Jf => leaders.insert(addr + instr.size()),
+            _ => false,
+        };
+    }
+    leaders.retain(|addr| cfg.code.contains_key(addr));
+
+    let mut src = String::new();
+    writeln!(src, "// Generated by aoc::intcode::aot::translate, don't edit").unwrap();
+    writeln!(src, "pub fn {}<M: Memory>(r: &mut Regs<M>) -> Exit {{", name).unwrap();
+    writeln!(src, "{}const CODE: &[(usize, &[i64])] = &[", INDENT).unwrap();
+    for &(start, end) in &runs {
+        writeln!(src, "{}({}, &[{}]),", INDENT.repeat(2), start, prog[start..end.min(prog.len())].iter().join(", ")).unwrap();
+    }
+    writeln!(src, "{}];\n", INDENT).unwrap();
+    writeln!(src, "{}fn is_code(addr: usize) -> bool {{", INDENT).unwrap();
+    let ranges = runs.iter().map(|&(start, end)| format!("{}..={}", start, end - 1)).join(" | ");
+    writeln!(src, "{}matches!(addr, {})", INDENT.repeat(2), ranges).unwrap();
+    writeln!(src, "{}}}\n", INDENT).unwrap();
+    writeln!(src, "{}if !r.code_matches(CODE) {{", INDENT).unwrap();
+    writeln!(src, "{}return Exit::Fallback;", INDENT.repeat(2)).unwrap();
+    writeln!(src, "{}}}", INDENT).unwrap();
+    writeln!(src, "{}let mut ip = r.ip;", INDENT).unwrap();
+    writeln!(src, "{}loop {{", INDENT).unwrap();
+    writeln!(src, "{}match ip {{", INDENT.repeat(2)).unwrap();
+    for &leader in &leaders {
+        writeln!(src, "{}{} => {{", INDENT.repeat(3), leader).unwrap();
+        for line in block(&cfg, &leaders, leader) {
+            writeln!(src, "{}{}", INDENT.repeat(4), line).unwrap();
+        }
+        writeln!(src, "{}}},", INDENT.repeat(3)).unwrap();
+    }
+    writeln!(src, "{}_ => return r.fallback(ip, 0),", INDENT.repeat(3)).unwrap();
+    writeln!(src, "{}}}", INDENT.repeat(2)).unwrap();
+    writeln!(src, "{}}}", INDENT).unwrap();
+    writeln!(src, "}}").unwrap();
+    Ok(src)
+}
+
+/// Lines of the dispatch arm of the block that starts at `leader`
+fn block(cfg: &Cfg, leaders: &BTreeSet<usize>, leader: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut addr = leader;
+    // instructions executed in the block so far
+    let mut count = 0;
+
+    loop {
+        let instr = cfg.code[&addr];
+        let next = addr + instr.size();
+        let fallback = format!("return r.fallback({}, {})", addr, count);
+        lines.push(format!("// {}: {}", addr, instr));
+
+        let load = |i: usize| -> String {
+            let param = instr.params()[i];
+            match param.mode {
+                Mode::Imm => param.value.to_string(),
+                Mode::Pos => format!("r.mem.get({})", param.value),
+                Mode::Rel => format!("match r.rel({}) {{ Some(a) => r.mem.get(a), None => {} }}", param.value, fallback),
+            }
+        };
+        let dest = |i: usize| -> Option<String> {
+            let param = instr.params()[i];
+            match param.mode {
+                Mode::Pos if param.value >= 0 => Some(format!("let d = {};", param.value)),
+                Mode::Rel => Some(format!("let d = match r.rel({}) {{ Some(a) if !is_code(a) => a, _ => {} }};", param.value, fallback)),
+                _ => None,
+            }
+        };
+        let negative_pos = instr.params().iter().any(|p| p.mode == Mode::Pos && p.value < 0);
+
+        match instr.opcode {
+            // left to the interpreter to report the fault
+            _ if negative_pos => {
+                lines.push(format!("{};", fallback));
+                return lines;
+            },
+            Opcode::Add | Opcode::Mul | Opcode::Lt | Opcode::Eq => {
+                let Some(dest) = dest(2) else {
+                    lines.push(format!("{};", fallback));
+                    return lines;
+                };
+                lines.push(format!("let (x, y): (i64, i64) = ({}, {});", load(0), load(1)));
+                match instr.opcode {
+                    Opcode::Add => lines.push(format!("let Some(v) = x.checked_add(y) else {{ {} }};", fallback)),
+                    Opcode::Mul => lines.push(format!("let Some(v) = x.checked_mul(y) else {{ {} }};", fallback)),
+                    Opcode::Lt => lines.push("let v = (x < y) as i64;".to_string()),
+                    _ => lines.push("let v = (x == y) as i64;".to_string()),
+                }
+                lines.push(dest);
+                lines.push("r.mem.set(d, v);".to_string());
+            },
+            Opcode::In => {
+                let Some(dest) = dest(0) else {
+                    lines.push(format!("{};", fallback));
+                    return lines;
+                };
+                lines.push(dest);
+                // IN starts a block, so there's no instruction count to update
+                lines.push("let Some(v) = r.input.pop_front() else {".to_string());
+                lines.push(format!("{}r.ip = {};", INDENT, addr));
+                lines.push(format!("{}return Exit::WaitInput;", INDENT));
+                lines.push("};".to_string());
+                lines.push("r.mem.set(d, v);".to_string());
+            },
+            Opcode::Out => lines.push(format!("r.output.push_back({});", load(0))),
+            Opcode::Arb => {
+                lines.push(format!("let Some(rb) = r.rel_base.checked_add({}) else {{ {} }};", load(0), fallback));
+                lines.push("r.rel_base = rb;".to_string());
+            },
+            Opcode::Hlt => {
+                lines.push(format!("r.ip = {};", addr));
+                lines.push(format!("r.instr_count += {};", count + 1));
+                lines.push("return Exit::Halt;".to_string());
+                return lines;
+            },
+            Opcode::Jt | Opcode::Jf => {
+                let cmp = if instr.opcode == Opcode::Jt { "!=" } else { "==" };
+                // a computed target is only loaded if the jump is taken
+                let load_target = |indent: &str| [
+                    format!("{}let t = {};", indent, load(1)),
+                    format!("{}if t < 0 {{ {} }}", indent, fallback),
+                ];
+                match (branch_outcomes(&instr), imm_target(&instr)) {
+                    ((false, _), _) => lines.push(format!("ip = {};", next)),
+                    ((true, false), Some(target)) => lines.push(format!("ip = {};", target)),
+                    ((true, false), None) => {
+                        lines.extend(load_target(""));
+                        lines.push("ip = t;".to_string());
+                    },
+                    ((true, true), Some(target)) => {
+                        lines.push(format!("ip = if {} {} 0 {{ {} }} else {{ {} }};", load(0), cmp, target, next));
+                    },
+                    ((true, true), None) => {
+                        lines.push(format!("ip = if {} {} 0 {{", load(0), cmp));
+                        lines.extend(load_target(INDENT));
+                        lines.push(format!("{}t", INDENT));
+                        lines.push(format!("}} else {{ {} }};", next));
+                    },
+                }
+                lines.push(format!("r.instr_count += {};", count + 1));
+                return lines;
+            },
+        }
+
+        count += 1;
+        addr = next;
+        if leaders.contains(&addr) || !cfg.code.contains_key(&addr) {
+            lines.push(format!("r.instr_count += {};", count));
+            lines.push(format!("ip = {};", addr));
+            return lines;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // running the translated code is tested in the aoc-aot crate
+    #[test]
+    fn test_translate_self_modifying() {
+        // writes into the code are refused by the static analysis
+        assert_eq!(translate(&[1101, 1, 1, 0, 99], "f"), Err(vec![
+            Diagnostic::SelfModifying { ip: 0, addr: 0, target: 0 },
+        ]));
+    }
+}
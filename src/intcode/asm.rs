@@ -0,0 +1,511 @@
+//! Assembler for a small Intcode assembly language.
+//!
+//! The syntax follows the `disasm` listings:
+//!
+//! ```text
+//! ; comments start with a semicolon
+//! %macro mov src, dst         ; macros take comma separated parameters
+//!     ADD src, #0, dst
+//! %endmacro
+//!
+//! start:  IN [x]              ; [addr] is position mode
+//!         mov [x], rb+1       ; rb+n / rb-n is relative mode
+//!         JT #1, #start       ; #val is immediate mode
+//! x:      DB 0                ; data: numbers, labels or "strings"
+//! ```
+//!
+//! Mnemonics are case insensitive. Operand values are expressions made of
+//! numbers and labels added or subtracted, like `#end-start` or `[buf+2]`.
+//! Labels starting with `%%` inside a macro are local to each expansion.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use itertools::Itertools;
+use super::{Instr, Mode, Opcode, Param};
+
+/// Max nesting of macro expansions, to catch recursive macros
+const MAX_MACRO_DEPTH: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    /// Line of the source where the error was found, starting at 1
+    pub line: usize,
+    pub msg: String,
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<String>,
+}
+
+/// A source line after expanding macros, with the line number it came from
+struct Line {
+    line: usize,
+    text: String,
+}
+
+enum Item {
+    Instr(Opcode, Vec<(Mode, Expr)>),
+    Data(Vec<Expr>),
+}
+
+/// Sum of terms, each one a number or a label, with its sign
+struct Expr(Vec<(i64, Term)>);
+
+enum Term {
+    Num(i64),
+    Label(String),
+}
+
+/// Assemble a program, returning its memory image
+pub fn assemble(src: &str) -> Result<Vec<i64>, AsmError> {
+    let lines = expand_macros(src)?;
+
+    // first pass: parse and assign addresses to labels
+    let mut labels = HashMap::new();
+    let mut items = Vec::new();
+    let mut addr = 0;
+    for Line { line, text } in lines {
+        let err = |msg: String| AsmError { line, msg };
+        let mut text = text.as_str();
+
+        while let Some((label, rest)) = split_label(text) {
+            if labels.insert(label.to_string(), addr as i64).is_some() {
+                return Err(err(format!("duplicated label '{}'", label)));
+            }
+            text = rest;
+        }
+        if text.is_empty() {
+            continue;
+        }
+
+        let item = parse_item(text).map_err(err)?;
+        addr += match &item {
+            Item::Instr(opcode, _) => 1 + opcode.param_count(),
+            Item::Data(exprs) => exprs.len(),
+        };
+        items.push((line, item));
+    }
+
+    // second pass: resolve expressions and encode
+    let mut prog = Vec::with_capacity(addr);
+    for (line, item) in items {
+        let eval = |expr: &Expr| expr.eval(&labels).map_err(|msg| AsmError { line, msg });
+        match item {
+            Item::Instr(opcode, operands) => {
+                let params: Vec<Param> = operands.iter()
+                    .map(|(mode, expr)| Ok(Param { mode: *mode, value: eval(expr)? }))
+                    .collect::<Result<_, _>>()?;
+                prog.extend(Instr::new(opcode, &params).encode());
+            },
+            Item::Data(exprs) => {
+                for expr in &exprs {
+                    prog.push(eval(expr)?);
+                }
+            },
+        }
+    }
+
+    Ok(prog)
+}
+
+/// Format a program in the comma separated format used by the input files
+pub fn to_text(prog: &[i64]) -> String {
+    prog.iter().join(",")
+}
+
+/// Strip comments, collect macro definitions and expand their invocations
+fn expand_macros(src: &str) -> Result<Vec<Line>, AsmError> {
+    let mut macros = HashMap::new();
+    let mut lines = Vec::new();
+    let mut defining: Option<(usize, String, Macro)> = None;
+
+    for (i, text) in src.lines().enumerate() {
+        let line = i + 1;
+        let text = strip_comment(text).trim();
+        let (first, rest) = split_first_word(text);
+
+        if first.eq_ignore_ascii_case("%macro") {
+            if defining.is_some() {
+                return Err(AsmError { line, msg: "nested macro definition".to_string() });
+            }
+            let (name, params) = split_first_word(rest);
+            if !is_ident(name) {
+                return Err(AsmError { line, msg: format!("invalid macro name '{}'", name) });
+            }
+            let params = split_operands(params).into_iter().map(String::from).collect();
+            defining = Some((line, name.to_lowercase(), Macro { params, body: Vec::new() }));
+        } else if first.eq_ignore_ascii_case("%endmacro") || first.eq_ignore_ascii_case("%endm") {
+            let (start, name, mac) = defining.take()
+                .ok_or(AsmError { line, msg: "%endmacro without %macro".to_string() })?;
+            if macros.contains_key(&name) {
+                return Err(AsmError { line: start, msg: format!("duplicated macro '{}'", name) });
+            }
+            macros.insert(name, mac);
+        } else if let Some((_, _, mac)) = &mut defining {
+            mac.body.push(text.to_string());
+        } else {
+            lines.push(Line { line, text: text.to_string() });
+        }
+    }
+
+    if let Some((line, name, _)) = defining {
+        return Err(AsmError { line, msg: format!("macro '{}' without %endmacro", name) });
+    }
+
+    let mut expanded = Vec::new();
+    let mut count = 0;
+    for line in lines {
+        expand_line(line, &macros, &mut count, 0, &mut expanded)?;
+    }
+    Ok(expanded)
+}
+
+fn expand_line(line: Line, macros: &HashMap<String, Macro>, count: &mut usize, depth: usize,
+               expanded: &mut Vec<Line>) -> Result<(), AsmError> {
+    // labels before a macro invocation stay in the invoking line
+    let mut text = line.text.as_str();
+    let mut labels = String::new();
+    while let Some((label, rest)) = split_label(text) {
+        labels += label;
+        labels += ": ";
+        text = rest;
+    }
+
+    let (name, args) = split_first_word(text);
+    let mac = match macros.get(&name.to_lowercase()) {
+        Some(mac) => mac,
+        None => {
+            expanded.push(line);
+            return Ok(());
+        }
+    };
+
+    if depth == MAX_MACRO_DEPTH {
+        return Err(AsmError { line: line.line, msg: "too many nested macro expansions".to_string() });
+    }
+    let args = split_operands(args);
+    if args.len() != mac.params.len() {
+        let msg = format!("macro '{}' takes {} arguments, got {}", name, mac.params.len(), args.len());
+        return Err(AsmError { line: line.line, msg });
+    }
+
+    *count += 1;
+    let local_prefix = format!("__{}_{}_", name.to_lowercase(), count);
+    if !labels.is_empty() {
+        expanded.push(Line { line: line.line, text: labels });
+    }
+    for body_line in &mac.body {
+        let text = replace_idents(body_line, |ident| {
+            if let Some(local) = ident.strip_prefix("%%") {
+                return Some(format!("{}{}", local_prefix, local));
+            }
+            let i = mac.params.iter().position(|p| p == ident)?;
+            Some(args[i].to_string())
+        });
+        expand_line(Line { line: line.line, text }, macros, count, depth + 1, expanded)?;
+    }
+    Ok(())
+}
+
+fn parse_item(text: &str) -> Result<Item, String> {
+    let (mnemonic, operands) = split_first_word(text);
+    let operands = split_operands(operands);
+
+    if mnemonic.eq_ignore_ascii_case("db") {
+        let mut exprs = Vec::new();
+        for operand in operands {
+            match operand.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+                Some(string) => exprs.extend(string.chars().map(|c| Expr::num(c as i64))),
+                None => exprs.push(Expr::parse(operand)?),
+            }
+        }
+        return Ok(Item::Data(exprs));
+    }
+
+    let opcode = Opcode::ALL.into_iter()
+        .find(|op| op.mnemonic().eq_ignore_ascii_case(mnemonic))
+        .ok_or(format!("unknown mnemonic '{}'", mnemonic))?;
+    if operands.len() != opcode.param_count() {
+        return Err(format!("{} takes {} operands, got {}",
+                           opcode.mnemonic(), opcode.param_count(), operands.len()));
+    }
+
+    let operands = operands.into_iter()
+        .map(parse_operand)
+        .collect::<Result<Vec<_>, _>>()?;
+    if let Some(i) = opcode.dest_param() {
+        if operands[i].0 == Mode::Imm {
+            return Err(format!("{} can't write to immediate operand {}", opcode.mnemonic(), i + 1));
+        }
+    }
+
+    Ok(Item::Instr(opcode, operands))
+}
+
+fn parse_operand(text: &str) -> Result<(Mode, Expr), String> {
+    if let Some(expr) = text.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+        return Ok((Mode::Pos, Expr::parse(expr)?));
+    }
+    if let Some(expr) = text.strip_prefix('#') {
+        return Ok((Mode::Imm, Expr::parse(expr)?));
+    }
+    if text.get(..2).is_some_and(|prefix| prefix.eq_ignore_ascii_case("rb")) {
+        let offset = text[2..].trim_start();
+        if offset.is_empty() {
+            return Ok((Mode::Rel, Expr::num(0)));
+        }
+        if offset.starts_with(['+', '-']) {
+            return Ok((Mode::Rel, Expr::parse(offset)?));
+        }
+    }
+    Err(format!("invalid operand '{}', expected [addr], #value or rb+offset", text))
+}
+
+impl Expr {
+    fn num(val: i64) -> Expr {
+        Expr(vec![(1, Term::Num(val))])
+    }
+
+    fn parse(text: &str) -> Result<Expr, String> {
+        let mut terms = Vec::new();
+        let mut sign = 1;
+        let mut start = 0;
+
+        // split in terms at each '+' or '-' that is not the sign of the first term
+        let bytes = text.as_bytes();
+        for i in 0..=bytes.len() {
+            if i < bytes.len() && !(bytes[i] == b'+' || bytes[i] == b'-') {
+                continue;
+            }
+            let term = text[start..i].trim();
+            if term.is_empty() {
+                if i < bytes.len() && terms.is_empty() && text[..i].trim().is_empty() {
+                    sign = if bytes[i] == b'-' { -1 } else { 1 };
+                    start = i + 1;
+                    continue;
+                }
+                return Err(format!("invalid expression '{}'", text.trim()));
+            }
+
+            // parsed with its sign, so that i64::MIN fits
+            let signed = if sign < 0 { format!("-{}", term) } else { term.to_string() };
+            let term = match signed.parse() {
+                Ok(num) => (1, Term::Num(num)),
+                Err(_) if is_ident(term) => (sign, Term::Label(term.to_string())),
+                Err(_) => return Err(format!("invalid value '{}'", term)),
+            };
+            terms.push(term);
+            if i < bytes.len() {
+                sign = if bytes[i] == b'-' { -1 } else { 1 };
+                start = i + 1;
+            }
+        }
+
+        Ok(Expr(terms))
+    }
+
+    fn eval(&self, labels: &HashMap<String, i64>) -> Result<i64, String> {
+        let mut total: i64 = 0;
+        for (sign, term) in &self.0 {
+            let val = match term {
+                Term::Num(num) => *num,
+                Term::Label(label) => *labels.get(label)
+                    .ok_or(format!("undefined label '{}'", label))?,
+            };
+            total = val.checked_mul(*sign)
+                .and_then(|val| total.checked_add(val))
+                .ok_or("expression overflows")?;
+        }
+        Ok(total)
+    }
+}
+
+/// If the line starts with `label:`, split it from the rest of the line
+fn split_label(text: &str) -> Option<(&str, &str)> {
+    let (label, rest) = text.split_once(':')?;
+    let label = label.trim();
+    is_ident(label).then(|| (label, rest.trim()))
+}
+
+fn split_first_word(text: &str) -> (&str, &str) {
+    let text = text.trim();
+    match text.split_once(char::is_whitespace) {
+        Some((first, rest)) => (first, rest.trim()),
+        None => (text, ""),
+    }
+}
+
+/// Remove the comment at the end of a line, if any, respecting strings
+fn strip_comment(text: &str) -> &str {
+    let mut in_string = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            ';' if !in_string => return &text[..i],
+            _ => (),
+        }
+    }
+    text
+}
+
+/// Split comma separated operands, respecting strings
+fn split_operands(text: &str) -> Vec<&str> {
+    let mut operands = Vec::new();
+    let mut in_string = false;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            ',' if !in_string => {
+                operands.push(text[start..i].trim());
+                start = i + 1;
+            },
+            _ => (),
+        }
+    }
+    if !text[start..].trim().is_empty() || !operands.is_empty() {
+        operands.push(text[start..].trim());
+    }
+    operands
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '%'
+}
+
+fn is_ident(text: &str) -> bool {
+    text.chars().next().is_some_and(|c| !c.is_ascii_digit()) && text.chars().all(is_ident_char)
+}
+
+/// Replace the identifiers in `text` for which `replace` returns something,
+/// leaving strings untouched
+fn replace_idents(text: &str, replace: impl Fn(&str) -> Option<String>) -> String {
+    let mut result = String::new();
+    let mut chars = text.char_indices().peekable();
+    let mut in_string = false;
+
+    while let Some((i, c)) = chars.next() {
+        if c == '"' {
+            in_string = !in_string;
+        }
+        if in_string || !is_ident_char(c) {
+            result.push(c);
+            continue;
+        }
+        let mut end = i + c.len_utf8();
+        while let Some(&(j, c)) = chars.peek() {
+            if !is_ident_char(c) {
+                break;
+            }
+            end = j + c.len_utf8();
+            chars.next();
+        }
+        let ident = &text[i..end];
+        match replace(ident) {
+            Some(replacement) => result += &replacement,
+            None => result += ident,
+        }
+    }
+
+    result
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.msg)
+    }
+}
+
+impl Error for AsmError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{disasm, Machine, State};
+
+    #[test]
+    fn test_assemble() {
+        let src = "
+            ; sum the inputs until reading a 0
+            loop:   IN [x]
+                    JF [x], #end
+                    ADD [x], [sum], [sum]
+                    JT #1, #loop
+            end:    OUT [sum]
+                    HLT
+            x:      DB 0
+            sum:    DB 0
+        ";
+        let prog = assemble(src).unwrap();
+        assert_eq!(to_text(&prog), "3,15,1006,15,12,1,15,16,16,1105,1,0,4,16,99,0,0");
+
+        let mut machine = Machine::new(&prog);
+        machine.extend_input([1, 2, 3, 0]);
+        assert_eq!(machine.run(), Ok(State::Halt));
+        assert_eq!(machine.pop_output(), Some(6));
+    }
+
+    #[test]
+    fn test_roundtrip_disasm() {
+        let src = "ADD #3, rb-2, [7]\nMUL rb, [0], rb+4\nHLT\nDB -1, 2";
+        let prog = assemble(src).unwrap();
+        assert_eq!(prog, [2101, 3, -2, 7, 20202, 0, 0, 4, 99, -1, 2]);
+
+        let listing = disasm(&prog);
+        let reassembled: String = listing.lines()
+            .filter(|line| !line.ends_with(':'))
+            .map(|line| line[40..].to_string() + "\n")
+            .collect();
+        assert_eq!(assemble(&reassembled).unwrap(), prog);
+    }
+
+    #[test]
+    fn test_macros_and_data() {
+        let src = "
+            %macro jmp target
+                JT #1, target
+            %endmacro
+            %macro countdown var
+            %%loop: ADD [var], #-1, [var]
+                    OUT [var]
+                    JT [var], #%%loop
+            %endm
+
+                    countdown n
+                    countdown n2
+                    jmp #end
+            n:      DB 2
+            msg:    DB \"a,b;\", 10, msg+1   ; not part of the string
+            n2:     db 1
+            end:    hlt
+        ";
+        let prog = assemble(src).unwrap();
+        assert_eq!(&prog[21..], [2, 97, 44, 98, 59, 10, 23, 1, 99]);
+
+        let mut machine = Machine::new(&prog);
+        assert_eq!(machine.run(), Ok(State::Halt));
+        assert_eq!(machine.drain_output().collect::<Vec<_>>(), [1, 0, 0]);
+
+        let prog = assemble("DB -9223372036854775808, 1 - 9223372036854775808, 9223372036854775807").unwrap();
+        assert_eq!(prog, [i64::MIN, i64::MIN + 1, i64::MAX]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |src| assemble(src).unwrap_err().to_string();
+        assert_eq!(error("HLT\nJT #1, #nowhere"), "line 2: undefined label 'nowhere'");
+        assert_eq!(error("a: HLT\na: HLT"), "line 2: duplicated label 'a'");
+        assert_eq!(error("NOP"), "line 1: unknown mnemonic 'NOP'");
+        assert_eq!(error("OUT #1, #2"), "line 1: OUT takes 1 operands, got 2");
+        assert_eq!(error("IN #1"), "line 1: IN can't write to immediate operand 1");
+        assert_eq!(error("OUT 5"), "line 1: invalid operand '5', expected [addr], #value or rb+offset");
+        assert_eq!(error("OUT [1+]"), "line 1: invalid expression '1+'");
+        assert_eq!(error("%macro m\nm\n%endm\nm"), "line 4: too many nested macro expansions");
+        assert_eq!(error("\n%macro m"), "line 2: macro 'm' without %endmacro");
+        assert_eq!(error("%macro m\n%endm\n%macro M\n%endm"), "line 3: duplicated macro 'm'");
+        assert_eq!(error("OUT €"), "line 1: invalid operand '€', expected [addr], #value or rb+offset");
+        assert_eq!(error("OUT r€"), "line 1: invalid operand 'r€', expected [addr], #value or rb+offset");
+    }
+}
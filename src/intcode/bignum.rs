@@ -0,0 +1,147 @@
+//! Arbitrary precision ADD and MUL with `Arithmetic::Big`, behind the
+//! `bignum` feature.
+//!
+//! Memory stays made of i64 cells: a result that doesn't fit is stored
+//! saturated and its exact value is kept in `Machine::big`, until the cell is
+//! written again. ADD, MUL, LT and EQ read the exact values of their
+//! operands, JT and JF only need to know that a big value is not 0, and the
+//! other uses of a big value fault since they need an i64.
+
+use num_bigint::{BigInt, Sign};
+use super::{Instr, IntcodeError, Machine, Memory, Opcode, Step};
+
+impl<M: Memory> Machine<M> {
+    /// Read a memory cell directly, with the exact value of a cell that
+    /// doesn't fit in an i64
+    pub fn peek_big(&self, addr: usize) -> BigInt {
+        match self.big.get(&addr) {
+            Some(val) => val.clone(),
+            None => BigInt::from(self.peek(addr)),
+        }
+    }
+
+    /// Write a memory cell directly, also with a value that doesn't fit in
+    /// an i64
+    pub fn poke_big(&mut self, addr: usize, val: BigInt) {
+        match i64::try_from(&val) {
+            Ok(val) => self.poke(addr, val),
+            Err(_) => {
+                self.poke(addr, if val.sign() == Sign::Minus { i64::MIN } else { i64::MAX });
+                self.big.insert(addr, val);
+            },
+        }
+    }
+
+    /// Addresses and exact values of the cells that don't fit in an i64
+    pub fn big_cells(&self) -> impl Iterator<Item = (usize, &BigInt)> + '_ {
+        self.big.iter().map(|(&addr, val)| (addr, val))
+    }
+
+    /// Fail if the cell at `addr` is needed as an i64 by the instruction
+    /// `instr` but doesn't fit
+    pub(super) fn check_small(&self, instr: i64, addr: usize) -> Result<(), IntcodeError> {
+        match self.big.contains_key(&addr) {
+            true => Err(IntcodeError::BigValue { ip: self.ip, instr, addr }),
+            false => Ok(()),
+        }
+    }
+
+    /// Execute ADD, MUL, LT or EQ on the exact values of the operands
+    pub(super) fn exec_big(&mut self, instr: Instr) -> Result<Step, IntcodeError> {
+        let lhs = self.peek_big(self.param_addr(&instr, 0)?);
+        let rhs = self.peek_big(self.param_addr(&instr, 1)?);
+        let val = match instr.opcode {
+            Opcode::Add => lhs + rhs,
+            Opcode::Mul => lhs * rhs,
+            Opcode::Lt => BigInt::from((lhs < rhs) as i64),
+            Opcode::Eq => BigInt::from((lhs == rhs) as i64),
+            opcode => unreachable!("{:?} is not executed with big values", opcode),
+        };
+        let addr = self.dest_addr(&instr, 2)?;
+        self.poke_big(addr, val);
+
+        self.ip += instr.size() as i64;
+        self.instr_count += 1;
+        Ok(Step::Exec(instr))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{Arithmetic, State};
+
+    // [x] = 2^input with repeated doublings, then compare it with 2^63 - 1
+    // and output it, which faults if it doesn't fit in an i64
+    const POW2: [i64; 23] = [
+        3, 22, 1002, 21, 2, 21, 1001, 22, -1, 22, 1005, 22, 2,
+        1008, 21, i64::MAX, 20, 4, 21, 99, 0, 1, 0,
+    ];
+
+    fn run_pow2(arithmetic: Arithmetic, exp: i64) -> (Machine, Result<State, IntcodeError>) {
+        let mut machine = Machine::new(&POW2);
+        machine.set_arithmetic(arithmetic);
+        machine.push_input(exp);
+        let res = machine.run();
+        (machine, res)
+    }
+
+    #[test]
+    fn test_big_arithmetic() {
+        let (machine, res) = run_pow2(Arithmetic::Big, 10);
+        assert_eq!(res, Ok(State::Halt));
+        assert_eq!(machine.output(), &[1024]);
+        assert_eq!(machine.big_cells().count(), 0);
+
+        // the comparison sees the exact value, and outputting it faults
+        let (mut machine, res) = run_pow2(Arithmetic::Big, 100);
+        assert_eq!(res, Err(IntcodeError::BigValue { ip: 17, instr: 4, addr: 21 }));
+        assert_eq!(machine.peek(20), 0);
+        assert_eq!(machine.peek(21), i64::MAX);
+        assert_eq!(machine.peek_big(21), BigInt::from(2).pow(100));
+        assert_eq!(machine.big_cells().collect::<Vec<_>>(), [(21, &(BigInt::from(2).pow(100)))]);
+
+        // forks keep the big values, and writing the cell forgets its big value
+        let mut fork = machine.fork();
+        assert_eq!(fork.peek_big(21), machine.peek_big(21));
+        machine.poke(21, 5);
+        assert_eq!(machine.peek_big(21), BigInt::from(5));
+        assert_eq!(machine.run(), Ok(State::Halt));
+        assert_eq!(machine.output(), &[5]);
+        fork.poke_big(21, -BigInt::from(2).pow(70));
+        assert_eq!(fork.peek(21), i64::MIN);
+
+        // the other policies never produce big values
+        let (machine, res) = run_pow2(Arithmetic::Saturating, 100);
+        assert_eq!(res, Ok(State::Halt));
+        assert_eq!((machine.peek(20), machine.output()), (1, &[i64::MAX].into()));
+        assert_eq!(machine.big_cells().count(), 0);
+        assert!(matches!(run_pow2(Arithmetic::Checked, 100).1, Err(IntcodeError::Overflow { .. })));
+    }
+
+    #[test]
+    fn test_big_value_faults() {
+        let big = BigInt::from(1u64 << 63);
+        let run = |prog: &[i64], addr| {
+            let mut machine = Machine::new(prog);
+            machine.set_arithmetic(Arithmetic::Big);
+            machine.poke_big(addr, big.clone());
+            machine.run()
+        };
+
+        // as an instruction, a position parameter, a jump target and a rel_base adjustment
+        assert_eq!(run(&[1105, 1, 3, 0], 3), Err(IntcodeError::BigValue { ip: 3, instr: i64::MAX, addr: 3 }));
+        assert_eq!(run(&[4, 0, 99], 1), Err(IntcodeError::BigValue { ip: 0, instr: 4, addr: 1 }));
+        assert_eq!(run(&[1105, 1, 0, 99], 2), Err(IntcodeError::BigValue { ip: 0, instr: 1105, addr: 2 }));
+        assert_eq!(run(&[109, 0, 99], 1), Err(IntcodeError::BigValue { ip: 0, instr: 109, addr: 1 }));
+
+        // but big immediates can be added, and are not 0 for a jump
+        let mut machine = Machine::new(&[1101, 0, -1, 7, 1105, 0, 8, 0, 99]);
+        machine.set_arithmetic(Arithmetic::Big);
+        machine.poke_big(1, big.clone());
+        machine.poke_big(5, big);
+        assert_eq!(machine.run(), Ok(State::Halt));
+        assert_eq!(machine.peek_big(7), BigInt::from(i64::MAX));
+        assert_eq!(machine.big_cells().count(), 2);
+    }
+}
@@ -0,0 +1,460 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use super::analyze::{branch_outcomes, imm_target, Cfg};
+use super::{Instr, Mode, Opcode, Param};
+
+const INDENT: &str = "    ";
+
+/// Lift a program into C-like pseudo-code, one function per block of code
+/// that is called, plus `main` from address 0.
+///
+/// Calls are recognized from the idiom of the day 09 programs: the caller
+/// stores the return address at `rb+0` and jumps to the function, which
+/// starts with an `ARB #n` prologue and ends with `ARB #-n` and a jump to
+/// `rb+0`. Inside such a function, the cells of its frame are named `p1`,
+/// `p2`... (the caller's `rb[1]`, `rb[2]`...) and the ones after it `out1`,
+/// `out2`..., the arguments of its own calls. Jumps are lifted to `if`,
+/// `else`, `while` and `do`/`while` when they nest properly, and to `goto`
+/// otherwise. Cells that are not code are named `v<addr>`, and the ones that
+/// are `mem[<addr>]`.
+///
+/// Like `disasm`, this only sees the code that can be followed statically.
+pub fn decompile(prog: &[i64]) -> String {
+    let cfg = Cfg::build(prog);
+    let mut funcs = Vec::new();
+    let mut entries = BTreeSet::from([0]);
+    let mut pending = vec![0];
+
+    while let Some(entry) = pending.pop() {
+        let func = Func::new(&cfg, entry);
+        for &callee in func.calls.values() {
+            if entries.insert(callee) {
+                pending.push(callee);
+            }
+        }
+        funcs.push(func);
+    }
+    funcs.sort_by_key(|func| func.entry);
+
+    funcs.iter().map(|func| func.to_source()).collect::<Vec<_>>().join("\n")
+}
+
+struct Func<'a> {
+    cfg: &'a Cfg,
+    entry: usize,
+    /// Instructions reachable from the entry without following calls, sorted
+    /// by address
+    body: Vec<(usize, Instr)>,
+    /// Called function by address of the jump that calls it
+    calls: BTreeMap<usize, usize>,
+    /// Size of the frame, if the function only moves `rb` in its prologue
+    /// and before returning
+    frame: Option<i64>,
+}
+
+/// Where `break` and `continue` jump to inside the loop being lifted
+#[derive(Clone, Copy, Default)]
+struct Loop {
+    brk: Option<usize>,
+    cont: Option<usize>,
+}
+
+enum Line {
+    /// Label of an address, printed only if some `goto` jumps to it
+    Label(usize),
+    Stmt(usize, String),
+}
+
+impl<'a> Func<'a> {
+    fn new(cfg: &'a Cfg, entry: usize) -> Func<'a> {
+        let mut body = BTreeMap::new();
+        let mut calls = BTreeMap::new();
+        let mut pending = vec![entry];
+
+        while let Some(addr) = pending.pop() {
+            let Some(&instr) = cfg.code.get(&addr).filter(|_| !body.contains_key(&addr)) else {
+                continue;
+            };
+            body.insert(addr, instr);
+
+            let next = addr + instr.size();
+            match instr.opcode {
+                Opcode::Hlt => (),
+                Opcode::Jt | Opcode::Jf => {
+                    if let Some(callee) = call_target(cfg, addr) {
+                        calls.insert(addr, callee);
+                        pending.push(next);
+                        continue;
+                    }
+                    let (taken, not_taken) = branch_outcomes(&instr);
+                    if let (true, Some(target)) = (taken, imm_target(&instr)) {
+                        pending.push(target);
+                    }
+                    if not_taken {
+                        pending.push(next);
+                    }
+                },
+                _ => pending.push(next),
+            }
+        }
+
+        let mut func = Func { cfg, entry, body: body.into_iter().collect(), calls, frame: None };
+        func.frame = func.find_frame();
+        func
+    }
+
+    fn find_frame(&self) -> Option<i64> {
+        let (addr, prologue) = *self.body.first().filter(|(addr, _)| *addr == self.entry && self.entry != 0)?;
+        let size = prologue.params().first()
+            .filter(|p| prologue.opcode == Opcode::Arb && p.mode == Mode::Imm && p.value > 0)?
+            .value;
+
+        let only_epilogues = self.body.iter().enumerate()
+            .filter(|(_, (a, instr))| instr.opcode == Opcode::Arb && *a != addr)
+            .all(|(i, (_, instr))| {
+                instr.params()[0] == Param { mode: Mode::Imm, value: -size }
+                    && self.body.get(i + 1).is_some_and(|(_, next)| is_return_jump(next, 0))
+            });
+        only_epilogues.then_some(size)
+    }
+
+    fn to_source(&self) -> String {
+        let mut lines = Vec::new();
+        let mut gotos = BTreeSet::new();
+        self.block(0, self.body.len(), Loop::default(), 1, &mut lines, &mut gotos);
+
+        let mut src = String::new();
+        match self.entry {
+            0 => writeln!(src, "main() {{").unwrap(),
+            entry => writeln!(src, "f{}() {{", entry).unwrap(),
+        }
+        if let Some(size) = self.frame.filter(|&size| size > 1) {
+            let slots = (1..size).map(|i| format!("p{}", i)).collect::<Vec<_>>().join(", ");
+            writeln!(src, "{}// frame: {}", INDENT, slots).unwrap();
+        }
+        for line in lines {
+            match line {
+                Line::Label(addr) if gotos.contains(&addr) => writeln!(src, "L{}:", addr).unwrap(),
+                Line::Label(_) => (),
+                Line::Stmt(depth, stmt) => writeln!(src, "{}{}", INDENT.repeat(depth), stmt).unwrap(),
+            }
+        }
+        writeln!(src, "}}").unwrap();
+        src
+    }
+
+    /// Lift the instructions `body[lo..hi]`
+    fn block(&self, lo: usize, hi: usize, lp: Loop, depth: usize, lines: &mut Vec<Line>, gotos: &mut BTreeSet<usize>) {
+        let stmt = |lines: &mut Vec<Line>, text: String| lines.push(Line::Stmt(depth, text));
+        let mut i = lo;
+
+        while i < hi {
+            let (addr, instr) = self.body[i];
+            lines.push(Line::Label(addr));
+
+            // a jump back to here makes a loop, unless it's the one being lifted
+            let back_jump = (i..hi).rev().find(|&j| self.jump_target(j) == Some(addr));
+            if let Some(j) = back_jump.filter(|_| lp.cont != Some(addr) || i != lo) {
+                let inner = Loop { brk: Some(self.end(j)), cont: Some(addr) };
+                let (_, back) = self.body[j];
+                if branch_outcomes(&back) == (true, false) {
+                    if i < j && self.jump_target(i) == inner.brk && branch_outcomes(&instr) == (true, true) {
+                        stmt(lines, format!("while ({}) {{", self.cond(&instr, false)));
+                        self.block(i + 1, j, inner, depth + 1, lines, gotos);
+                    } else {
+                        stmt(lines, "while (1) {".to_string());
+                        self.block(i, j, inner, depth + 1, lines, gotos);
+                    }
+                    stmt(lines, "}".to_string());
+                } else {
+                    stmt(lines, "do {".to_string());
+                    self.block(i, j, inner, depth + 1, lines, gotos);
+                    stmt(lines, format!("}} while ({});", self.cond(&back, true)));
+                }
+                i = j + 1;
+                continue;
+            }
+
+            match instr.opcode {
+                Opcode::Jt | Opcode::Jf => {
+                    i = self.jump(i, hi, lp, depth, lines, gotos);
+                    continue;
+                },
+                // the return address stored before a call
+                Opcode::Add | Opcode::Mul if i + 1 < hi && self.calls.contains_key(&self.end(i)) => (),
+                // prologue and epilogue
+                Opcode::Arb if self.frame.is_some() => (),
+                _ => stmt(lines, self.stmt(&instr)),
+            }
+            i += 1;
+        }
+    }
+
+    /// Lift the jump at `body[i]`, and the code it skips if it makes an `if`,
+    /// returning the index of the next instruction to lift
+    fn jump(&self, i: usize, hi: usize, lp: Loop, depth: usize, lines: &mut Vec<Line>, gotos: &mut BTreeSet<usize>)
+            -> usize {
+        let stmt = |lines: &mut Vec<Line>, text: String| lines.push(Line::Stmt(depth, text));
+        let (addr, instr) = self.body[i];
+        let (taken, not_taken) = branch_outcomes(&instr);
+        let conditional = |text: String| match not_taken {
+            true => format!("if ({}) {}", self.cond(&instr, true), text),
+            false => text,
+        };
+
+        if let Some(callee) = self.calls.get(&addr) {
+            stmt(lines, format!("f{}();", callee));
+            return i + 1;
+        }
+        if !taken {
+            return i + 1;
+        }
+        let Some(target) = imm_target(&instr) else {
+            let ret = self.frame.is_some_and(|size| is_return_jump(&instr, 0) || is_return_jump(&instr, -size));
+            let text = match ret {
+                true => "return;".to_string(),
+                false => format!("goto *{};", self.operand(instr.params()[1])),
+            };
+            stmt(lines, conditional(text));
+            return i + 1;
+        };
+
+        if Some(target) == lp.brk || Some(target) == lp.cont {
+            let text = if Some(target) == lp.brk { "break;" } else { "continue;" };
+            stmt(lines, conditional(text.to_string()));
+            return i + 1;
+        }
+
+        let t = self.index(target).filter(|&t| t > i && t <= hi).filter(|&t| !self.entered(i + 1, t, i));
+        match t {
+            Some(t) if not_taken => {
+                // jumping over a block that ends jumping further makes an if/else
+                let skip_else = (t > i + 1).then(|| self.jump_target(t - 1)).flatten()
+                    .filter(|_| branch_outcomes(&self.body[t - 1].1) == (true, false))
+                    .filter(|&end| Some(end) != lp.brk && Some(end) != lp.cont)
+                    .and_then(|end| self.index(end))
+                    .filter(|&e| e > t && e <= hi && !self.entered(t, e, i));
+
+                stmt(lines, format!("if ({}) {{", self.cond(&instr, false)));
+                match skip_else {
+                    Some(e) => {
+                        self.block(i + 1, t - 1, lp, depth + 1, lines, gotos);
+                        stmt(lines, "} else {".to_string());
+                        self.block(t, e, lp, depth + 1, lines, gotos);
+                        stmt(lines, "}".to_string());
+                        e
+                    },
+                    None => {
+                        self.block(i + 1, t, lp, depth + 1, lines, gotos);
+                        stmt(lines, "}".to_string());
+                        t
+                    },
+                }
+            },
+            // a jump to the next instruction does nothing
+            Some(t) if t == i + 1 => t,
+            _ => {
+                gotos.insert(target);
+                stmt(lines, conditional(format!("goto L{};", target)));
+                i + 1
+            },
+        }
+    }
+
+    fn stmt(&self, instr: &Instr) -> String {
+        let params = instr.params();
+        let operand = |i: usize| self.operand(params[i]);
+        let is_imm = |i: usize, val: i64| params[i] == Param { mode: Mode::Imm, value: val };
+
+        match instr.opcode {
+            Opcode::Add if is_imm(0, 0) => format!("{} = {};", operand(2), operand(1)),
+            Opcode::Add if is_imm(1, 0) => format!("{} = {};", operand(2), operand(0)),
+            Opcode::Add if params[1].mode == Mode::Imm && params[1].value < 0 && params[1].value != i64::MIN => {
+                format!("{} = {} - {};", operand(2), operand(0), -params[1].value)
+            },
+            Opcode::Add => format!("{} = {} + {};", operand(2), operand(0), operand(1)),
+            Opcode::Mul if is_imm(0, 1) => format!("{} = {};", operand(2), operand(1)),
+            Opcode::Mul if is_imm(1, 1) => format!("{} = {};", operand(2), operand(0)),
+            Opcode::Mul => format!("{} = {} * {};", operand(2), operand(0), operand(1)),
+            Opcode::In => format!("{} = input();", operand(0)),
+            Opcode::Out => format!("output({});", operand(0)),
+            Opcode::Lt => format!("{} = {} < {};", operand(2), operand(0), operand(1)),
+            Opcode::Eq => format!("{} = {} == {};", operand(2), operand(0), operand(1)),
+            Opcode::Arb => format!("rb += {};", operand(0)),
+            Opcode::Hlt => "halt();".to_string(),
+            Opcode::Jt | Opcode::Jf => unreachable!("jumps are lifted by Func::jump"),
+        }
+    }
+
+    fn operand(&self, param: Param) -> String {
+        match (param.mode, self.frame) {
+            (Mode::Imm, _) => param.value.to_string(),
+            (Mode::Pos, _) => match usize::try_from(param.value).ok().and_then(|addr| self.cfg.instr_at(addr)) {
+                None if param.value >= 0 => format!("v{}", param.value),
+                _ => format!("mem[{}]", param.value),
+            },
+            (Mode::Rel, Some(size)) if param.value == -size => "ret".to_string(),
+            (Mode::Rel, Some(size)) if param.value > -size && param.value < 0 => format!("p{}", param.value + size),
+            (Mode::Rel, Some(_)) if param.value > 0 => format!("out{}", param.value),
+            (Mode::Rel, _) => format!("rb[{}]", param.value),
+        }
+    }
+
+    /// Condition for the jump `instr` to be taken, or not
+    fn cond(&self, instr: &Instr, taken: bool) -> String {
+        let val = self.operand(instr.params()[0]);
+        match (instr.opcode == Opcode::Jt) == taken {
+            true => val,
+            false => format!("!{}", val),
+        }
+    }
+
+    /// Target of the jump at `body[i]`, if it's a jump that can be taken to
+    /// an immediate address and not a call
+    fn jump_target(&self, i: usize) -> Option<usize> {
+        let (addr, instr) = self.body[i];
+        let is_jump = matches!(instr.opcode, Opcode::Jt | Opcode::Jf) && !self.calls.contains_key(&addr);
+        if !is_jump || !branch_outcomes(&instr).0 {
+            return None;
+        }
+        imm_target(&instr)
+    }
+
+    /// Whether some jump from outside `body[lo..hi]`, other than the one at
+    /// `body[from]`, lands inside it
+    fn entered(&self, lo: usize, hi: usize, from: usize) -> bool {
+        let inside = |addr| self.index(addr).is_some_and(|k| k >= lo && k < hi);
+        (0..self.body.len())
+            .filter(|&k| k != from && !(lo..hi).contains(&k))
+            .any(|k| self.jump_target(k).is_some_and(inside))
+    }
+
+    fn index(&self, addr: usize) -> Option<usize> {
+        self.body.binary_search_by_key(&addr, |&(a, _)| a).ok()
+    }
+
+    /// Address right after the instruction at `body[i]`
+    fn end(&self, i: usize) -> usize {
+        let (addr, instr) = self.body[i];
+        addr + instr.size()
+    }
+}
+
+/// If the jump at `addr` is a call, the address of the called function. A
+/// call is an unconditional jump right after storing the address after it.
+fn call_target(cfg: &Cfg, addr: usize) -> Option<usize> {
+    let jump = cfg.code.get(&addr)?;
+    let store = cfg.code.get(&addr.checked_sub(4)?)?;
+    if branch_outcomes(jump) != (true, false) || !matches!(store.opcode, Opcode::Add | Opcode::Mul) {
+        return None;
+    }
+
+    let params = store.params();
+    if params[0].mode != Mode::Imm || params[1].mode != Mode::Imm {
+        return None;
+    }
+    let ret_addr = match store.opcode {
+        Opcode::Add => params[0].value.checked_add(params[1].value)?,
+        _ => params[0].value.checked_mul(params[1].value)?,
+    };
+    (ret_addr == (addr + jump.size()) as i64).then(|| imm_target(jump)).flatten()
+}
+
+/// Whether `instr` always jumps to the address at `rb+offset`
+fn is_return_jump(instr: &Instr, offset: i64) -> bool {
+    matches!(instr.opcode, Opcode::Jt | Opcode::Jf)
+        && branch_outcomes(instr) == (true, false)
+        && instr.params()[1] == Param { mode: Mode::Rel, value: offset }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::asm::assemble;
+
+    #[test]
+    fn test_decompile() {
+        let prog = assemble("
+                    ARB #100
+                    IN [n]
+                    ADD [n], #0, rb+1
+                    ADD #ret, #0, rb+0
+                    JT #1, #sum
+            ret:    OUT rb+1
+                    HLT
+
+            sum:    ARB #3
+                    ADD #0, #0, rb-1
+            loop:   JF rb-2, #done
+                    ADD rb-1, rb-2, rb-1
+                    ADD rb-2, #-1, rb-2
+                    JT #1, #loop
+            done:   LT rb-1, #100, [flag]
+                    JT [flag], #small
+                    ADD #100, #0, rb-2
+                    JT #1, #end
+            small:  ADD rb-1, #0, rb-2
+            end:    ARB #-3
+                    JT #1, rb+0
+
+            n:      DB 0
+            flag:   DB 0
+        ").unwrap();
+
+        let expect = concat!(
+            "main() {\n",
+            "    rb += 100;\n",
+            "    v61 = input();\n",
+            "    rb[1] = v61;\n",
+            "    f18();\n",
+            "    output(rb[1]);\n",
+            "    halt();\n",
+            "}\n",
+            "\n",
+            "f18() {\n",
+            "    // frame: p1, p2\n",
+            "    p2 = 0;\n",
+            "    while (p1) {\n",
+            "        p2 = p2 + p1;\n",
+            "        p1 = p1 - 1;\n",
+            "    }\n",
+            "    v62 = p2 < 100;\n",
+            "    if (!v62) {\n",
+            "        p1 = 100;\n",
+            "    } else {\n",
+            "        p1 = p2;\n",
+            "    }\n",
+            "    return;\n",
+            "}\n",
+        );
+        assert_eq!(decompile(&prog), expect);
+    }
+
+    #[test]
+    fn test_decompile_goto() {
+        // a jump into the middle of a loop can't be structured
+        let prog = assemble("
+                    IN [x]
+                    JT [x], #mid
+            top:    OUT [x]
+            mid:    ADD [x], #-1, [x]
+                    JT [x], #top
+                    HLT
+            x:      DB 0
+        ").unwrap();
+
+        let expect = concat!(
+            "main() {\n",
+            "    v15 = input();\n",
+            "    if (v15) goto L7;\n",
+            "    do {\n",
+            "        output(v15);\n",
+            "L7:\n",
+            "        v15 = v15 - 1;\n",
+            "    } while (v15);\n",
+            "    halt();\n",
+            "}\n",
+        );
+        assert_eq!(decompile(&prog), expect);
+    }
+}
@@ -0,0 +1,118 @@
+use std::fmt::Write;
+use itertools::Itertools;
+use super::Instr;
+use super::analyze::Cfg;
+
+/// Max number of values printed in a single data line
+const DATA_PER_LINE: usize = 8;
+
+/// Disassemble a program into an annotated listing.
+///
+/// Only the instructions that are reachable following the control flow from
+/// address 0 are decoded, the rest of the cells are listed as `DB` data.
+/// This is a best-effort split: jumps to computed addresses can't be followed,
+/// so returning from a call is guessed from the common idiom of storing a
+/// constant return address right before an unconditional jump.
+/// Jump targets are preceded by a `L<addr>:` label line.
+pub fn disasm(prog: &[i64]) -> String {
+    let Cfg { code, targets, .. } = Cfg::build(prog);
+    let mut listing = String::new();
+    let mut data = Vec::new();
+    let mut addr = 0;
+
+    while addr < prog.len() {
+        match code.get(&addr) {
+            Some(instr) => {
+                if targets.contains(&addr) {
+                    writeln!(listing, "L{}:", addr).unwrap();
+                }
+                writeln!(listing, "{}", format_instr(prog, addr, instr)).unwrap();
+
+                // code may also start inside the operands, if a jump lands there
+                for inner in addr + 1..addr + instr.size() {
+                    match (targets.contains(&inner), code.get(&inner)) {
+                        (is_target, Some(inner_instr)) => {
+                            if is_target {
+                                writeln!(listing, "L{}:", inner).unwrap();
+                            }
+                            let line = format_instr(prog, inner, inner_instr);
+                            writeln!(listing, "{}  ; overlaps the instruction at {}", line, addr).unwrap();
+                        },
+                        (true, None) => writeln!(listing, "L{}:  ; inside the instruction at {}", inner, addr).unwrap(),
+                        (false, None) => (),
+                    }
+                }
+                addr += instr.size();
+            },
+            None => {
+                data.push(prog[addr]);
+                addr += 1;
+            },
+        }
+
+        let data_end = addr >= prog.len() || code.contains_key(&addr);
+        if data.len() == DATA_PER_LINE || (data_end && !data.is_empty()) {
+            let data_addr = addr - data.len();
+            writeln!(listing, "{:>6}  {:<32}DB {}", data_addr, "", data.iter().join(", ")).unwrap();
+            data.clear();
+        }
+    }
+
+    listing
+}
+
+fn format_instr(prog: &[i64], addr: usize, instr: &Instr) -> String {
+    let raw = prog[addr..(addr + instr.size()).min(prog.len())].iter().join(",");
+    format!("{:>6}  {:<32}{}", addr, raw, instr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disasm() {
+        let prog = [
+            3, 11,          // IN [11]
+            1005, 11, 8,    // JT [11], #8
+            104, -1, 99,    // OUT #-1; HLT
+            204, -2, 99,    // OUT rb-2; HLT
+            0, 7, 7,
+        ];
+        let expect = concat!(
+            "     0  3,11                            IN [11]\n",
+            "     2  1005,11,8                       JT [11], #8\n",
+            "     5  104,-1                          OUT #-1\n",
+            "     7  99                              HLT\n",
+            "L8:\n",
+            "     8  204,-2                          OUT rb-2\n",
+            "    10  99                              HLT\n",
+            "    11                                  DB 0, 7, 7\n",
+        );
+        assert_eq!(disasm(&prog), expect);
+    }
+
+    #[test]
+    fn test_disasm_call() {
+        // the address stored before the jump is the return address of a call
+        let prog = [21101, 7, 0, 1, 1105, 1, 8, 99, 2106, 0, 1];
+        let listing = disasm(&prog);
+        assert!(listing.contains("L7:\n     7  99"));
+        assert!(listing.contains("L8:\n     8  2106,0,1"));
+    }
+
+    #[test]
+    fn test_disasm_overlap() {
+        // the jump lands on the operand of OUT
+        let prog = [1005, 7, 4, 104, 99, 99, 0, 0];
+        let expect = concat!(
+            "     0  1005,7,4                        JT [7], #4\n",
+            "     3  104,99                          OUT #99\n",
+            "L4:\n",
+            "     4  99                              HLT  ; overlaps the instruction at 3\n",
+            "     5  99                              HLT\n",
+            "     6                                  DB 0, 0\n",
+        );
+        assert_eq!(disasm(&prog), expect);
+    }
+}
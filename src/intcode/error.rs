@@ -0,0 +1,87 @@
+use std::error::Error;
+use std::fmt;
+
+/// Fault raised while executing an Intcode instruction.
+///
+/// Every variant carries the address of the faulting instruction (`ip`) and
+/// its raw instruction word (`instr`), plus the value that caused the fault.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntcodeError {
+    /// `instr % 100` is not a known opcode
+    UnknownOpcode { ip: i64, instr: i64, opcode: i64 },
+    /// A parameter mode digit is not 0 (position), 1 (immediate) or 2 (relative),
+    /// or there are mode digits for parameters that the opcode doesn't take
+    InvalidMode { ip: i64, instr: i64, mode: i64 },
+    /// A parameter or jump target resolved to an address below 0
+    NegativeAddress { ip: i64, instr: i64, addr: i64 },
+    /// The parameter number `param` (1-based) is written to, but is in immediate mode
+    WriteToImmediate { ip: i64, instr: i64, param: usize },
+    /// A read instruction was reached with no input left and no way to wait for more
+    InputExhausted { ip: i64, instr: i64 },
+    /// `lhs` and `rhs` can't be added or multiplied without overflowing an i64
+    Overflow { ip: i64, instr: i64, lhs: i64, rhs: i64 },
+    /// The value at `addr` doesn't fit in an i64 but is used where one is
+    /// needed, see `Arithmetic::Big`
+    #[cfg(feature = "bignum")]
+    BigValue { ip: i64, instr: i64, addr: usize },
+}
+
+impl IntcodeError {
+    pub fn ip(&self) -> i64 {
+        match *self {
+            IntcodeError::UnknownOpcode { ip, .. }
+            | IntcodeError::InvalidMode { ip, .. }
+            | IntcodeError::NegativeAddress { ip, .. }
+            | IntcodeError::WriteToImmediate { ip, .. }
+            | IntcodeError::InputExhausted { ip, .. }
+            | IntcodeError::Overflow { ip, .. } => ip,
+            #[cfg(feature = "bignum")]
+            IntcodeError::BigValue { ip, .. } => ip,
+        }
+    }
+
+    pub fn instr(&self) -> i64 {
+        match *self {
+            IntcodeError::UnknownOpcode { instr, .. }
+            | IntcodeError::InvalidMode { instr, .. }
+            | IntcodeError::NegativeAddress { instr, .. }
+            | IntcodeError::WriteToImmediate { instr, .. }
+            | IntcodeError::InputExhausted { instr, .. }
+            | IntcodeError::Overflow { instr, .. } => instr,
+            #[cfg(feature = "bignum")]
+            IntcodeError::BigValue { instr, .. } => instr,
+        }
+    }
+}
+
+impl fmt::Display for IntcodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            IntcodeError::UnknownOpcode { opcode, .. } => {
+                write!(f, "unknown opcode {}", opcode)?
+            },
+            IntcodeError::InvalidMode { mode, .. } => {
+                write!(f, "invalid parameter mode {}", mode)?
+            },
+            IntcodeError::NegativeAddress { addr, .. } => {
+                write!(f, "negative address {}", addr)?
+            },
+            IntcodeError::WriteToImmediate { param, .. } => {
+                write!(f, "write to parameter {} in immediate mode", param)?
+            },
+            IntcodeError::InputExhausted { .. } => {
+                write!(f, "read with no input available")?
+            },
+            IntcodeError::Overflow { lhs, rhs, .. } => {
+                write!(f, "arithmetic overflow with operands {} and {}", lhs, rhs)?
+            },
+            #[cfg(feature = "bignum")]
+            IntcodeError::BigValue { addr, .. } => {
+                write!(f, "value at address {} doesn't fit in an i64", addr)?
+            },
+        }
+        write!(f, " (instruction {} at ip {})", self.instr(), self.ip())
+    }
+}
+
+impl Error for IntcodeError {}
@@ -0,0 +1,211 @@
+use std::fmt;
+use super::IntcodeError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Opcode {
+    Add,
+    Mul,
+    In,
+    Out,
+    Jt,
+    Jf,
+    Lt,
+    Eq,
+    Arb,
+    Hlt,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mode {
+    Pos,
+    Imm,
+    Rel,
+}
+
+/// A decoded parameter: its addressing mode and the raw value stored in the
+/// instruction (an address, an immediate value or an offset from `rel_base`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Param {
+    pub mode: Mode,
+    pub value: i64,
+}
+
+/// A decoded instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Instr {
+    pub opcode: Opcode,
+    /// Raw instruction word, with the opcode and parameter modes
+    pub word: i64,
+    params: [Param; 3],
+}
+
+impl Opcode {
+    pub const ALL: [Opcode; 10] = [
+        Opcode::Add, Opcode::Mul, Opcode::In, Opcode::Out, Opcode::Jt,
+        Opcode::Jf, Opcode::Lt, Opcode::Eq, Opcode::Arb, Opcode::Hlt,
+    ];
+
+    pub fn from_code(code: i64) -> Option<Opcode> {
+        match code {
+            1  => Some(Opcode::Add),
+            2  => Some(Opcode::Mul),
+            3  => Some(Opcode::In),
+            4  => Some(Opcode::Out),
+            5  => Some(Opcode::Jt),
+            6  => Some(Opcode::Jf),
+            7  => Some(Opcode::Lt),
+            8  => Some(Opcode::Eq),
+            9  => Some(Opcode::Arb),
+            99 => Some(Opcode::Hlt),
+            _  => None,
+        }
+    }
+
+    pub fn code(self) -> i64 {
+        match self {
+            Opcode::Add => 1,
+            Opcode::Mul => 2,
+            Opcode::In  => 3,
+            Opcode::Out => 4,
+            Opcode::Jt  => 5,
+            Opcode::Jf  => 6,
+            Opcode::Lt  => 7,
+            Opcode::Eq  => 8,
+            Opcode::Arb => 9,
+            Opcode::Hlt => 99,
+        }
+    }
+
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            Opcode::Add => "ADD",
+            Opcode::Mul => "MUL",
+            Opcode::In  => "IN",
+            Opcode::Out => "OUT",
+            Opcode::Jt  => "JT",
+            Opcode::Jf  => "JF",
+            Opcode::Lt  => "LT",
+            Opcode::Eq  => "EQ",
+            Opcode::Arb => "ARB",
+            Opcode::Hlt => "HLT",
+        }
+    }
+
+    pub fn param_count(self) -> usize {
+        match self {
+            Opcode::Add | Opcode::Mul | Opcode::Lt | Opcode::Eq => 3,
+            Opcode::Jt | Opcode::Jf => 2,
+            Opcode::In | Opcode::Out | Opcode::Arb => 1,
+            Opcode::Hlt => 0,
+        }
+    }
+
+    /// Index of the parameter that the instruction writes to, if any
+    pub fn dest_param(self) -> Option<usize> {
+        match self {
+            Opcode::Add | Opcode::Mul | Opcode::Lt | Opcode::Eq => Some(2),
+            Opcode::In => Some(0),
+            _ => None,
+        }
+    }
+}
+
+impl Mode {
+    pub fn from_digit(digit: i64) -> Option<Mode> {
+        match digit {
+            0 => Some(Mode::Pos),
+            1 => Some(Mode::Imm),
+            2 => Some(Mode::Rel),
+            _ => None,
+        }
+    }
+
+    pub fn digit(self) -> i64 {
+        match self {
+            Mode::Pos => 0,
+            Mode::Imm => 1,
+            Mode::Rel => 2,
+        }
+    }
+}
+
+impl Instr {
+    /// Build an instruction from its parts. `params` must have as many items
+    /// as the opcode takes.
+    pub fn new(opcode: Opcode, params: &[Param]) -> Instr {
+        assert_eq!(params.len(), opcode.param_count(), "wrong number of params for {:?}", opcode);
+        let mut instr = Instr {
+            opcode,
+            word: opcode.code(),
+            params: [Param { mode: Mode::Pos, value: 0 }; 3],
+        };
+        for (i, param) in params.iter().enumerate() {
+            instr.word += param.mode.digit() * 10_i64.pow(2 + i as u32);
+            instr.params[i] = *param;
+        }
+        instr
+    }
+
+    /// Decode the instruction at `addr`, reading memory through `read`
+    pub fn decode(addr: usize, read: impl Fn(usize) -> i64) -> Result<Instr, IntcodeError> {
+        let ip = addr as i64;
+        let word = read(addr);
+        let opcode = Opcode::from_code(word % 100)
+            .ok_or(IntcodeError::UnknownOpcode { ip, instr: word, opcode: word % 100 })?;
+
+        let n_params = opcode.param_count();
+        let unused_modes = word / 10_i64.pow(2 + n_params as u32);
+        if unused_modes != 0 {
+            return Err(IntcodeError::InvalidMode { ip, instr: word, mode: unused_modes });
+        }
+
+        let mut params = [Param { mode: Mode::Pos, value: 0 }; 3];
+        for (i, param) in params.iter_mut().enumerate().take(n_params) {
+            let digit = (word / 10_i64.pow(2 + i as u32)) % 10;
+            param.mode = Mode::from_digit(digit)
+                .ok_or(IntcodeError::InvalidMode { ip, instr: word, mode: digit })?;
+            param.value = read(addr + 1 + i);
+        }
+
+        Ok(Instr { opcode, word, params })
+    }
+
+    pub fn params(&self) -> &[Param] {
+        &self.params[..self.opcode.param_count()]
+    }
+
+    /// Number of memory cells taken by the instruction, including its parameters
+    pub fn size(&self) -> usize {
+        1 + self.opcode.param_count()
+    }
+
+    /// Memory cells that form the instruction: the instruction word followed
+    /// by the parameters
+    pub fn encode(&self) -> Vec<i64> {
+        std::iter::once(self.word)
+            .chain(self.params().iter().map(|p| p.value))
+            .collect()
+    }
+}
+
+impl fmt::Display for Param {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.mode {
+            Mode::Pos => write!(f, "[{}]", self.value),
+            Mode::Imm => write!(f, "#{}", self.value),
+            Mode::Rel if self.value < 0 => write!(f, "rb{}", self.value),
+            Mode::Rel => write!(f, "rb+{}", self.value),
+        }
+    }
+}
+
+impl fmt::Display for Instr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.opcode.mnemonic())?;
+        for (i, param) in self.params().iter().enumerate() {
+            let sep = if i == 0 { " " } else { ", " };
+            write!(f, "{}{}", sep, param)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,152 @@
+//! Sources and sinks of values for `Machine::run_io`, so a machine can be
+//! driven by a callback, an iterator or a channel instead of pushing input
+//! and draining output around each call to `run`.
+
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Write};
+use std::sync::mpsc::{Receiver, Sender, SyncSender};
+
+pub trait IntcodeInput {
+    /// Next input value, or `None` if there's none available (yet)
+    fn read(&mut self) -> Option<i64>;
+}
+
+pub trait IntcodeOutput {
+    fn write(&mut self, val: i64);
+}
+
+impl IntcodeInput for VecDeque<i64> {
+    fn read(&mut self) -> Option<i64> {
+        self.pop_front()
+    }
+}
+
+impl IntcodeOutput for VecDeque<i64> {
+    fn write(&mut self, val: i64) {
+        self.push_back(val);
+    }
+}
+
+impl IntcodeOutput for Vec<i64> {
+    fn write(&mut self, val: i64) {
+        self.push(val);
+    }
+}
+
+impl<F: FnMut() -> Option<i64>> IntcodeInput for F {
+    fn read(&mut self) -> Option<i64> {
+        self()
+    }
+}
+
+impl<F: FnMut(i64)> IntcodeOutput for F {
+    fn write(&mut self, val: i64) {
+        self(val)
+    }
+}
+
+/// Input taken from an iterator, i.e. `IterInput([5, 0].into_iter())`
+pub struct IterInput<I>(pub I);
+
+impl<I: Iterator<Item = i64>> IntcodeInput for IterInput<I> {
+    fn read(&mut self) -> Option<i64> {
+        self.0.next()
+    }
+}
+
+/// Block until a value is received. Returns `None` once all the senders are
+/// dropped and the channel is empty.
+impl IntcodeInput for Receiver<i64> {
+    fn read(&mut self) -> Option<i64> {
+        self.recv().ok()
+    }
+}
+
+/// Values sent after the receiver is dropped are discarded
+impl IntcodeOutput for Sender<i64> {
+    fn write(&mut self, val: i64) {
+        let _ = self.send(val);
+    }
+}
+
+/// Values sent after the receiver is dropped are discarded
+impl IntcodeOutput for SyncSender<i64> {
+    fn write(&mut self, val: i64) {
+        let _ = self.send(val);
+    }
+}
+
+/// Read one value per line from stdin, prompting for it. Lines that aren't
+/// a number are reported and skipped, and EOF ends the input.
+pub struct StdinInput {
+    pub prompt: &'static str,
+}
+
+impl IntcodeInput for StdinInput {
+    fn read(&mut self) -> Option<i64> {
+        let stdin = io::stdin();
+        loop {
+            print!("{}", self.prompt);
+            io::stdout().flush().ok()?;
+
+            let mut line = String::new();
+            if stdin.lock().read_line(&mut line).ok()? == 0 {
+                return None;
+            }
+            match line.trim().parse() {
+                Ok(val) => return Some(val),
+                Err(_) => eprintln!("Invalid input value '{}'", line.trim()),
+            }
+        }
+    }
+}
+
+/// Print one value per line to stdout
+pub struct StdoutOutput;
+
+impl IntcodeOutput for StdoutOutput {
+    fn write(&mut self, val: i64) {
+        println!("{}", val);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use crate::intcode::{Machine, State};
+
+    // out in * 3, until in is 0
+    const PROG: [i64; 16] = [3, 15, 1006, 15, 14, 1002, 15, 3, 15, 4, 15, 1105, 1, 0, 99, 0];
+
+    #[test]
+    fn test_run_io_iter_and_closure() {
+        let mut machine = Machine::new(&PROG);
+        let mut outputs = Vec::new();
+        let state = machine.run_io(&mut IterInput([1, 2, 3].into_iter()), &mut |val| outputs.push(val));
+        assert_eq!(state, Ok(State::WaitInput));
+        assert_eq!(outputs, [3, 6, 9]);
+
+        // the internal queues are used before asking the source
+        machine.push_input(4);
+        let mut queue = VecDeque::from([5, 0]);
+        let mut output = Vec::new();
+        assert_eq!(machine.run_io(&mut queue, &mut output), Ok(State::Halt));
+        assert_eq!(output, [12, 15]);
+    }
+
+    #[test]
+    fn test_run_io_channels() {
+        let (in_tx, mut in_rx) = mpsc::channel();
+        let (mut out_tx, out_rx) = mpsc::channel();
+        let handle = std::thread::spawn(move || {
+            Machine::new(&PROG).run_io(&mut in_rx, &mut out_tx)
+        });
+
+        in_tx.send(7).unwrap();
+        assert_eq!(out_rx.recv(), Ok(21));
+        drop(in_tx);
+        assert_eq!(handle.join().unwrap(), Ok(State::WaitInput));
+        assert!(out_rx.recv().is_err());
+    }
+}
@@ -0,0 +1,150 @@
+//! Memory backends for the Intcode machine. All of them are unbounded and
+//! read unwritten cells as 0, they only differ in performance:
+//! - `DenseMemory`: a `Vec` that grows on write, the fastest for the usual
+//!   programs that use a small range of addresses after the loaded image.
+//! - `PagedMemory`: fixed size pages allocated on write, for programs that
+//!   write to a few huge addresses.
+//! - `MapMemory`: a `HashMap` of cells, the original backend.
+
+use std::collections::HashMap;
+
+/// Number of cells that `DenseMemory` keeps in its `Vec`. Writes beyond this
+/// go to a map so a single write to a huge address doesn't exhaust memory.
+const DENSE_LIMIT: usize = 1 << 22;
+/// Number of cells per page in `PagedMemory`
+const PAGE_SIZE: usize = 1024;
+
+pub trait Memory: Default + Clone + Send {
+    fn get(&self, addr: usize) -> i64;
+    fn set(&mut self, addr: usize, val: i64);
+
+    /// Cells with a non-zero value, in no particular order
+    fn cells(&self) -> Box<dyn Iterator<Item = (usize, i64)> + '_>;
+
+    /// Memory with `prog` loaded from address 0
+    fn from_prog(prog: &[i64]) -> Self {
+        let mut mem = Self::default();
+        for (addr, &val) in prog.iter().enumerate() {
+            mem.set(addr, val);
+        }
+        mem
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DenseMemory {
+    cells: Vec<i64>,
+    far: HashMap<usize, i64>,
+}
+
+impl Memory for DenseMemory {
+    fn get(&self, addr: usize) -> i64 {
+        match self.cells.get(addr) {
+            Some(&val) => val,
+            None if addr < DENSE_LIMIT => 0,
+            None => self.far.get(&addr).copied().unwrap_or(0),
+        }
+    }
+
+    fn set(&mut self, addr: usize, val: i64) {
+        if addr >= DENSE_LIMIT {
+            self.far.insert(addr, val);
+            return;
+        }
+        if addr >= self.cells.len() {
+            if val == 0 {
+                return;
+            }
+            self.cells.resize(addr + 1, 0);
+        }
+        self.cells[addr] = val;
+    }
+
+    fn cells(&self) -> Box<dyn Iterator<Item = (usize, i64)> + '_> {
+        let near = self.cells.iter().copied().enumerate();
+        let far = self.far.iter().map(|(&addr, &val)| (addr, val));
+        Box::new(near.chain(far).filter(|&(_, val)| val != 0))
+    }
+
+    fn from_prog(prog: &[i64]) -> Self {
+        let mut mem = DenseMemory::default();
+        mem.cells.extend_from_slice(&prog[..prog.len().min(DENSE_LIMIT)]);
+        for (addr, &val) in prog.iter().enumerate().skip(DENSE_LIMIT) {
+            mem.far.insert(addr, val);
+        }
+        mem
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PagedMemory {
+    pages: HashMap<usize, Box<[i64; PAGE_SIZE]>>,
+}
+
+impl Memory for PagedMemory {
+    fn get(&self, addr: usize) -> i64 {
+        self.pages.get(&(addr / PAGE_SIZE)).map_or(0, |page| page[addr % PAGE_SIZE])
+    }
+
+    fn set(&mut self, addr: usize, val: i64) {
+        let page = match self.pages.get_mut(&(addr / PAGE_SIZE)) {
+            Some(page) => page,
+            None if val == 0 => return,
+            None => self.pages.entry(addr / PAGE_SIZE).or_insert_with(|| Box::new([0; PAGE_SIZE])),
+        };
+        page[addr % PAGE_SIZE] = val;
+    }
+
+    fn cells(&self) -> Box<dyn Iterator<Item = (usize, i64)> + '_> {
+        Box::new(self.pages.iter()
+            .flat_map(|(&n, page)| page.iter().enumerate().map(move |(i, &val)| (n * PAGE_SIZE + i, val)))
+            .filter(|&(_, val)| val != 0))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MapMemory {
+    cells: HashMap<usize, i64>,
+}
+
+impl Memory for MapMemory {
+    fn get(&self, addr: usize) -> i64 {
+        self.cells.get(&addr).copied().unwrap_or(0)
+    }
+
+    fn set(&mut self, addr: usize, val: i64) {
+        self.cells.insert(addr, val);
+    }
+
+    fn cells(&self) -> Box<dyn Iterator<Item = (usize, i64)> + '_> {
+        Box::new(self.cells.iter().map(|(&addr, &val)| (addr, val)).filter(|&(_, val)| val != 0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_memory<M: Memory>() {
+        let mut mem = M::from_prog(&[1, 2, 3]);
+        mem.set(5000, 7);
+        mem.set(usize::MAX, -1);
+        mem.set(1, 0);
+        assert_eq!(mem.get(0), 1);
+        assert_eq!(mem.get(1), 0);
+        assert_eq!(mem.get(4999), 0);
+        assert_eq!(mem.get(5000), 7);
+        assert_eq!(mem.get(usize::MAX), -1);
+
+        let mut cells: Vec<_> = mem.cells().collect();
+        cells.sort_unstable();
+        assert_eq!(cells, [(0, 1), (2, 3), (5000, 7), (usize::MAX, -1)]);
+    }
+
+    #[test]
+    fn test_memory_backends() {
+        check_memory::<DenseMemory>();
+        check_memory::<PagedMemory>();
+        check_memory::<MapMemory>();
+    }
+}
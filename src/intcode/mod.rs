@@ -0,0 +1,803 @@
+//! Intcode computer, shared by all the days that need to run Intcode programs.
+//!
+//! The machine implements the full instruction set as of day 09: the
+//! arithmetic and comparison instructions, conditional jumps, input/output
+//! and the relative base register. Memory is unbounded, so programs can read
+//! and write beyond the end of the loaded image (unwritten cells read as 0),
+//! with several backends to choose from in `memory`. Reading from an empty
+//! input queue pauses the machine with `State::WaitInput` so the caller can
+//! feed more values and resume.
+//!
+//! Malformed instructions never panic: they are reported as an
+//! `IntcodeError` and leave the machine untouched at the faulting
+//! instruction, so the caller can inspect it, patch memory or `set_ip` and
+//! resume, or just discard the machine.
+
+pub mod amp;
+mod analyze;
+pub mod aot;
+pub mod asm;
+#[cfg(feature = "bignum")]
+mod bignum;
+mod decompile;
+mod disasm;
+mod error;
+mod instr;
+pub mod io;
+pub mod memory;
+pub mod network;
+pub mod profile;
+pub mod replay;
+mod snapshot;
+pub mod symbolic;
+pub mod trace;
+mod undo;
+mod varint;
+
+use std::any::Any;
+#[cfg(feature = "bignum")]
+use std::collections::BTreeMap;
+use std::collections::VecDeque;
+use std::num::TryFromIntError;
+
+pub use analyze::{analyze, Diagnostic};
+pub use decompile::decompile;
+pub use disasm::disasm;
+pub use error::IntcodeError;
+pub use instr::{Instr, Mode, Opcode, Param};
+pub use io::{IntcodeInput, IntcodeOutput};
+pub use memory::{DenseMemory, Memory};
+pub use undo::UndoLog;
+
+use trace::{TraceOperand, TraceRecord, Tracer};
+
+/// Max number of addresses covered by the decode cache
+const DECODE_CACHE_LIMIT: usize = 1 << 16;
+/// Size of the largest instruction, so a write can modify the instructions
+/// that start up to `MAX_INSTR_SIZE - 1` cells before it
+const MAX_INSTR_SIZE: usize = 4;
+
+/// Intcode computer, generic over its memory backend. Use `Machine::new` for
+/// the default `DenseMemory`, or `with_memory` to choose another one.
+pub struct Machine<M = DenseMemory> {
+    mem: M,
+    ip: i64,
+    rel_base: i64,
+    input: VecDeque<i64>,
+    output: VecDeque<i64>,
+    halted: bool,
+    instr_count: u64,
+    tracer: Option<Box<dyn Tracer>>,
+    /// Instructions already decoded, by address. Writes to memory invalidate
+    /// the instructions that they overlap, so self-modifying code still works.
+    decode_cache: Vec<Option<Instr>>,
+    /// Addresses below this are cached, the cache grows up to it on demand
+    decode_cache_len: usize,
+    arithmetic: Arithmetic,
+    /// Values that don't fit in an i64, by address, see `Arithmetic::Big`.
+    /// Their cells in `mem` hold `i64::MIN` or `i64::MAX`.
+    #[cfg(feature = "bignum")]
+    big: BTreeMap<usize, num_bigint::BigInt>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Halt,
+    WaitInput,
+    /// The instruction budget given to `run_for` was used up
+    OutOfFuel,
+}
+
+/// How ADD and MUL handle results that don't fit in an `i64`. Addresses and
+/// `rel_base` adjustments are always checked, whatever the policy.
+///
+/// The arbitrary precision mode needs the `bignum` feature. Memory cells,
+/// instruction parameters and the I/O queues are still `i64` then: a bigger
+/// value is kept aside, read with `Machine::peek_big`, and using it as an
+/// instruction, address, jump target, `rel_base` adjustment or output value
+/// faults with `IntcodeError::BigValue`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Arithmetic {
+    /// Fault with `IntcodeError::Overflow`, leaving the machine untouched
+    #[default]
+    Checked,
+    /// Wrap around in two's complement, like release builds do with `+`/`*`
+    Wrapping,
+    /// Clamp to `i64::MIN`/`i64::MAX`
+    Saturating,
+    /// Keep the exact result, also in LT and EQ comparisons
+    #[cfg(feature = "bignum")]
+    Big,
+}
+
+/// Outcome of `Machine::step`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    /// This instruction was executed
+    Exec(Instr),
+    /// Nothing was executed because the machine is already halted
+    Halted,
+    /// Nothing was executed because the machine needs more input
+    WaitInput,
+}
+
+impl Machine {
+    pub fn new(prog: &[i64]) -> Machine {
+        Machine::with_memory(prog)
+    }
+}
+
+impl<M: Memory> Machine<M> {
+    pub fn with_memory(prog: &[i64]) -> Machine<M> {
+        let mut machine = Machine {
+            mem: M::from_prog(prog),
+            ip: 0,
+            rel_base: 0,
+            input: VecDeque::new(),
+            output: VecDeque::new(),
+            halted: false,
+            instr_count: 0,
+            tracer: None,
+            decode_cache: Vec::new(),
+            decode_cache_len: 0,
+            arithmetic: Arithmetic::default(),
+            #[cfg(feature = "bignum")]
+            big: BTreeMap::new(),
+        };
+        machine.set_decode_cache(prog.len());
+        machine
+    }
+
+    /// Copy of the machine that can run independently from this one, i.e. to
+    /// explore different inputs from the same state. The tracer, if any, is
+    /// not copied.
+    pub fn fork(&self) -> Machine<M> {
+        Machine {
+            mem: self.mem.clone(),
+            ip: self.ip,
+            rel_base: self.rel_base,
+            input: self.input.clone(),
+            output: self.output.clone(),
+            halted: self.halted,
+            instr_count: self.instr_count,
+            tracer: None,
+            decode_cache: self.decode_cache.clone(),
+            decode_cache_len: self.decode_cache_len,
+            arithmetic: self.arithmetic,
+            #[cfg(feature = "bignum")]
+            big: self.big.clone(),
+        }
+    }
+
+    /// Run until the program halts or needs an input value that is not
+    /// available yet. In the latter case, push more input and call `run` again
+    /// to resume from the pending read instruction.
+    ///
+    /// If an instruction faults, it is not executed and `ip` keeps pointing
+    /// to it.
+    pub fn run(&mut self) -> Result<State, IntcodeError> {
+        loop {
+            match self.step()? {
+                Step::Exec(_) if self.halted => return Ok(State::Halt),
+                Step::Exec(_) => (),
+                Step::Halted => return Ok(State::Halt),
+                Step::WaitInput => return Ok(State::WaitInput),
+            }
+        }
+    }
+
+    /// Like `run`, but execute `budget` instructions at most, returning
+    /// `State::OutOfFuel` if the program didn't stop by itself before. Call
+    /// `run_for` again to resume the execution.
+    pub fn run_for(&mut self, budget: u64) -> Result<State, IntcodeError> {
+        for _ in 0..budget {
+            match self.step()? {
+                Step::Exec(_) if self.halted => return Ok(State::Halt),
+                Step::Exec(_) => (),
+                Step::Halted => return Ok(State::Halt),
+                Step::WaitInput => return Ok(State::WaitInput),
+            }
+        }
+        match self.halted {
+            true => Ok(State::Halt),
+            false => Ok(State::OutOfFuel),
+        }
+    }
+
+    /// Like `run`, but read input from `input` when the input queue is empty
+    /// and write every output value to `output`, also the ones already in
+    /// the output queue. Returns `State::WaitInput` only when `input` has no
+    /// more values available.
+    pub fn run_io(&mut self, input: &mut impl IntcodeInput, output: &mut impl IntcodeOutput)
+            -> Result<State, IntcodeError> {
+        self.output.drain(..).for_each(|val| output.write(val));
+        loop {
+            match self.step()? {
+                Step::Exec(instr) => {
+                    if instr.opcode == Opcode::Out {
+                        self.output.drain(..).for_each(|val| output.write(val));
+                    }
+                    if self.halted {
+                        return Ok(State::Halt);
+                    }
+                },
+                Step::Halted => return Ok(State::Halt),
+                Step::WaitInput => match input.read() {
+                    Some(val) => self.input.push_back(val),
+                    None => return Ok(State::WaitInput),
+                },
+            }
+        }
+    }
+
+    /// Execute a single instruction, returning it decoded
+    pub fn step(&mut self) -> Result<Step, IntcodeError> {
+        if self.halted {
+            return Ok(Step::Halted);
+        }
+
+        let instr = self.fetch()?;
+        if self.tracer.is_none() {
+            return self.exec(instr);
+        }
+
+        let (count, ip, rel_base) = (self.instr_count, self.ip, self.rel_base);
+        let mut operands = [TraceOperand::default(); 3];
+        for (i, (param, operand)) in instr.params().iter().zip(operands.iter_mut()).enumerate() {
+            operand.addr = match param.mode {
+                Mode::Imm => None,
+                _ => self.param_addr(&instr, i).ok(),
+            };
+            operand.pre = operand.addr.map_or(param.value, |addr| self.peek(addr));
+        }
+
+        let step = self.exec(instr)?;
+        if let Step::Exec(_) = step {
+            for operand in operands.iter_mut() {
+                operand.post = operand.addr.map_or(operand.pre, |addr| self.peek(addr));
+            }
+            let record = TraceRecord::new(count, ip, rel_base, instr, &operands[..instr.params().len()]);
+            if let Some(tracer) = self.tracer.as_mut() {
+                tracer.trace(&record);
+            }
+        }
+        Ok(step)
+    }
+
+    fn exec(&mut self, instr: Instr) -> Result<Step, IntcodeError> {
+        #[cfg(feature = "bignum")]
+        if self.arithmetic == Arithmetic::Big
+                && matches!(instr.opcode, Opcode::Add | Opcode::Mul | Opcode::Lt | Opcode::Eq) {
+            return self.exec_big(instr);
+        }
+
+        match instr.opcode {
+            Opcode::Add => {
+                let (lhs, rhs) = (self.load(&instr, 0)?, self.load(&instr, 1)?);
+                let val = match self.arithmetic {
+                    Arithmetic::Checked => lhs.checked_add(rhs).ok_or_else(|| self.overflow(&instr, lhs, rhs))?,
+                    Arithmetic::Wrapping => lhs.wrapping_add(rhs),
+                    Arithmetic::Saturating => lhs.saturating_add(rhs),
+                    #[cfg(feature = "bignum")]
+                    Arithmetic::Big => unreachable!("run by exec_big"),
+                };
+                self.store(&instr, 2, val)?;
+            },
+            Opcode::Mul => {
+                let (lhs, rhs) = (self.load(&instr, 0)?, self.load(&instr, 1)?);
+                let val = match self.arithmetic {
+                    Arithmetic::Checked => lhs.checked_mul(rhs).ok_or_else(|| self.overflow(&instr, lhs, rhs))?,
+                    Arithmetic::Wrapping => lhs.wrapping_mul(rhs),
+                    Arithmetic::Saturating => lhs.saturating_mul(rhs),
+                    #[cfg(feature = "bignum")]
+                    Arithmetic::Big => unreachable!("run by exec_big"),
+                };
+                self.store(&instr, 2, val)?;
+            },
+            Opcode::In => {
+                // resolve the destination first so a fault doesn't consume input
+                let addr = self.dest_addr(&instr, 0)?;
+                match self.input.pop_front() {
+                    Some(val) => self.poke(addr, val),
+                    None => return Ok(Step::WaitInput),
+                }
+            },
+            Opcode::Out => {
+                self.output.push_back(self.load(&instr, 0)?);
+            },
+            Opcode::Jt => {
+                if self.is_nonzero(&instr, 0)? {
+                    self.ip = self.jump_target(&instr)?;
+                    self.instr_count += 1;
+                    return Ok(Step::Exec(instr));
+                }
+            },
+            Opcode::Jf => {
+                if !self.is_nonzero(&instr, 0)? {
+                    self.ip = self.jump_target(&instr)?;
+                    self.instr_count += 1;
+                    return Ok(Step::Exec(instr));
+                }
+            },
+            Opcode::Lt => {
+                let val = self.load(&instr, 0)? < self.load(&instr, 1)?;
+                self.store(&instr, 2, val as i64)?;
+            },
+            Opcode::Eq => {
+                let val = self.load(&instr, 0)? == self.load(&instr, 1)?;
+                self.store(&instr, 2, val as i64)?;
+            },
+            Opcode::Arb => {
+                let offset = self.load(&instr, 0)?;
+                self.rel_base = self.rel_base.checked_add(offset)
+                    .ok_or_else(|| self.overflow(&instr, self.rel_base, offset))?;
+            },
+            Opcode::Hlt => {
+                self.halted = true;
+            },
+        }
+
+        if !self.halted {
+            self.ip += instr.size() as i64;
+        }
+        self.instr_count += 1;
+        Ok(Step::Exec(instr))
+    }
+
+    /// Run until the program halts, failing with `IntcodeError::InputExhausted`
+    /// if it tries to read more input than what has been pushed.
+    pub fn run_to_halt(&mut self) -> Result<(), IntcodeError> {
+        match self.run()? {
+            State::Halt => Ok(()),
+            _ => Err(IntcodeError::InputExhausted {
+                ip: self.ip,
+                instr: self.peek(self.ip as usize),
+            }),
+        }
+    }
+
+    pub fn push_input(&mut self, val: i64) {
+        self.input.push_back(val);
+    }
+
+    pub fn extend_input(&mut self, vals: impl IntoIterator<Item = i64>) {
+        self.input.extend(vals);
+    }
+
+    pub fn pop_output(&mut self) -> Option<i64> {
+        self.output.pop_front()
+    }
+
+    pub fn drain_output(&mut self) -> impl Iterator<Item = i64> + '_ {
+        self.output.drain(..)
+    }
+
+    pub fn output_len(&self) -> usize {
+        self.output.len()
+    }
+
+    /// Pending input values, in the order they will be read
+    pub fn input(&self) -> &VecDeque<i64> {
+        &self.input
+    }
+
+    pub fn input_mut(&mut self) -> &mut VecDeque<i64> {
+        &mut self.input
+    }
+
+    /// Output values not consumed yet, in the order they were written
+    pub fn output(&self) -> &VecDeque<i64> {
+        &self.output
+    }
+
+    pub fn output_mut(&mut self) -> &mut VecDeque<i64> {
+        &mut self.output
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn ip(&self) -> i64 {
+        self.ip
+    }
+
+    pub fn rel_base(&self) -> i64 {
+        self.rel_base
+    }
+
+    /// Set a tracer that will receive a record of every executed instruction
+    pub fn set_tracer(&mut self, tracer: impl Tracer) {
+        self.tracer = Some(Box::new(tracer));
+    }
+
+    /// Remove the tracer and return it. If it's not of type `T`, it's left
+    /// in place and `None` is returned.
+    pub fn take_tracer<T: Tracer>(&mut self) -> Option<T> {
+        if !(self.tracer.as_deref()? as &dyn Any).is::<T>() {
+            return None;
+        }
+        let tracer: Box<dyn Any> = self.tracer.take()?;
+        tracer.downcast::<T>().ok().map(|tracer| *tracer)
+    }
+
+    /// The tracer, if it's of type `T`
+    pub fn tracer_mut<T: Tracer>(&mut self) -> Option<&mut T> {
+        (self.tracer.as_deref_mut()? as &mut dyn Any).downcast_mut::<T>()
+    }
+
+    /// Number of instructions executed since the machine was created
+    pub fn instr_count(&self) -> u64 {
+        self.instr_count
+    }
+
+    /// Move the instruction pointer, e.g. to skip a faulting instruction.
+    /// Fails if `ip` doesn't fit in an `i64`, leaving it untouched.
+    pub fn set_ip(&mut self, ip: usize) -> Result<(), TryFromIntError> {
+        self.ip = i64::try_from(ip)?;
+        Ok(())
+    }
+
+    /// Set how ADD and MUL handle overflows, `Arithmetic::Checked` by default
+    pub fn set_arithmetic(&mut self, arithmetic: Arithmetic) {
+        self.arithmetic = arithmetic;
+    }
+
+    pub fn arithmetic(&self) -> Arithmetic {
+        self.arithmetic
+    }
+
+    /// Cache the decoded instructions of the first `len` addresses (up to
+    /// 65536), where the program code is expected to be, or disable the
+    /// cache if `len` is 0. The machine caches the loaded image by default.
+    pub fn set_decode_cache(&mut self, len: usize) {
+        self.decode_cache.clear();
+        self.decode_cache_len = len.min(DECODE_CACHE_LIMIT);
+    }
+
+    /// Read a memory cell directly, without going through an instruction. A
+    /// value that doesn't fit in an i64 reads as `i64::MIN` or `i64::MAX`.
+    pub fn peek(&self, addr: usize) -> i64 {
+        self.mem.get(addr)
+    }
+
+    /// Write a memory cell directly, without going through an instruction
+    pub fn poke(&mut self, addr: usize, val: i64) {
+        self.mem.set(addr, val);
+        #[cfg(feature = "bignum")]
+        if !self.big.is_empty() {
+            self.big.remove(&addr);
+        }
+        let first = addr.saturating_sub(MAX_INSTR_SIZE - 1);
+        if first < self.decode_cache.len() {
+            let last = addr.min(self.decode_cache.len() - 1);
+            self.decode_cache[first..=last].fill(None);
+        }
+    }
+
+    /// Decode the instruction at ip, from the cache if possible
+    fn fetch(&mut self) -> Result<Instr, IntcodeError> {
+        let ip = self.ip as usize;
+        #[cfg(feature = "bignum")]
+        self.check_small(self.peek(ip), ip)?;
+        if let Some(&Some(instr)) = self.decode_cache.get(ip) {
+            return Ok(instr);
+        }
+        let instr = Instr::decode(ip, |addr| self.mem.get(addr))?;
+        if ip < self.decode_cache_len {
+            if ip >= self.decode_cache.len() {
+                self.decode_cache.resize(ip + 1, None);
+            }
+            self.decode_cache[ip] = Some(instr);
+        }
+        Ok(instr)
+    }
+
+    /// Resolve the address a parameter points to
+    fn param_addr(&self, instr: &Instr, i: usize) -> Result<usize, IntcodeError> {
+        let param = instr.params()[i];
+        #[cfg(feature = "bignum")]
+        if param.mode != Mode::Imm {
+            self.check_small(instr.word, self.ip as usize + 1 + i)?;
+        }
+        let addr = match param.mode {
+            Mode::Pos => param.value,
+            Mode::Imm => self.ip + 1 + i as i64,
+            Mode::Rel => self.rel_base.checked_add(param.value)
+                .ok_or_else(|| self.overflow(instr, self.rel_base, param.value))?,
+        };
+        match addr {
+            0.. => Ok(addr as usize),
+            _   => Err(IntcodeError::NegativeAddress { ip: self.ip, instr: instr.word, addr }),
+        }
+    }
+
+    fn load(&self, instr: &Instr, i: usize) -> Result<i64, IntcodeError> {
+        let addr = self.param_addr(instr, i)?;
+        #[cfg(feature = "bignum")]
+        self.check_small(instr.word, addr)?;
+        Ok(self.peek(addr))
+    }
+
+    /// Whether a parameter is not 0, which also works for big values since
+    /// they are saturated
+    fn is_nonzero(&self, instr: &Instr, i: usize) -> Result<bool, IntcodeError> {
+        Ok(self.peek(self.param_addr(instr, i)?) != 0)
+    }
+
+    fn store(&mut self, instr: &Instr, i: usize, val: i64) -> Result<(), IntcodeError> {
+        let addr = self.dest_addr(instr, i)?;
+        self.poke(addr, val);
+        Ok(())
+    }
+
+    fn dest_addr(&self, instr: &Instr, i: usize) -> Result<usize, IntcodeError> {
+        match instr.params()[i].mode {
+            Mode::Imm => Err(IntcodeError::WriteToImmediate { ip: self.ip, instr: instr.word, param: i + 1 }),
+            _ => self.param_addr(instr, i),
+        }
+    }
+
+    fn jump_target(&self, instr: &Instr) -> Result<i64, IntcodeError> {
+        match self.load(instr, 1)? {
+            addr @ 0.. => Ok(addr),
+            addr => Err(IntcodeError::NegativeAddress { ip: self.ip, instr: instr.word, addr }),
+        }
+    }
+
+    fn overflow(&self, instr: &Instr, lhs: i64, rhs: i64) -> IntcodeError {
+        IntcodeError::Overflow { ip: self.ip, instr: instr.word, lhs, rhs }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_with_input(prog: &[i64], input: &[i64]) -> Vec<i64> {
+        let mut machine = Machine::new(prog);
+        machine.extend_input(input.iter().copied());
+        assert_eq!(machine.run(), Ok(State::Halt));
+        machine.drain_output().collect()
+    }
+
+    #[test]
+    fn test_add_mul() {
+        let mut machine = Machine::new(&[1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50]);
+        assert_eq!(machine.run(), Ok(State::Halt));
+        assert_eq!(machine.peek(0), 3500);
+    }
+
+    #[test]
+    fn test_compare_and_jump() {
+        // output 999 if input < 8, 1000 if input == 8, 1001 if input > 8
+        let prog = [
+            3, 21, 1008, 21, 8, 20, 1005, 20, 22, 107, 8, 21, 20, 1006, 20, 31,
+            1106, 0, 36, 98, 0, 0, 1002, 21, 125, 20, 4, 20, 1105, 1, 46, 104,
+            999, 1105, 1, 46, 1101, 1000, 1, 20, 4, 20, 1105, 1, 46, 98, 99,
+        ];
+        assert_eq!(run_with_input(&prog, &[7]), [999]);
+        assert_eq!(run_with_input(&prog, &[8]), [1000]);
+        assert_eq!(run_with_input(&prog, &[9]), [1001]);
+    }
+
+    #[test]
+    fn test_rel_base_quine() {
+        let prog = [109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99];
+        assert_eq!(run_with_input(&prog, &[]), prog);
+    }
+
+    #[test]
+    fn test_large_numbers() {
+        assert_eq!(run_with_input(&[104, 1125899906842624, 99], &[]), [1125899906842624]);
+        assert_eq!(run_with_input(&[1102, 34915192, 34915192, 7, 4, 7, 99, 0], &[]), [1219070632396864]);
+    }
+
+    #[test]
+    fn test_wait_input() {
+        let mut machine = Machine::new(&[3, 20, 3, 21, 1, 20, 21, 22, 4, 22, 99]);
+        assert_eq!(machine.run(), Ok(State::WaitInput));
+        machine.push_input(3);
+        assert_eq!(machine.run(), Ok(State::WaitInput));
+        machine.push_input(4);
+        assert_eq!(machine.run(), Ok(State::Halt));
+        assert_eq!(machine.pop_output(), Some(7));
+    }
+
+    #[test]
+    fn test_step() {
+        let prog = [1101, 2, 3, 0, 3, 0, 99];
+        let decode = |addr| Instr::decode(addr, |a| prog[a]).unwrap();
+
+        let mut machine = Machine::new(&prog);
+        assert_eq!(machine.step(), Ok(Step::Exec(decode(0))));
+        assert_eq!((machine.ip(), machine.peek(0)), (4, 5));
+        assert_eq!(machine.step(), Ok(Step::WaitInput));
+        assert_eq!(machine.ip(), 4);
+        machine.push_input(1);
+        assert_eq!(machine.step(), Ok(Step::Exec(decode(4))));
+        assert_eq!(machine.step(), Ok(Step::Exec(decode(6))));
+        assert!(machine.is_halted());
+        assert_eq!(machine.step(), Ok(Step::Halted));
+        assert_eq!(machine.instr_count(), 3);
+    }
+
+    #[test]
+    fn test_run_for() {
+        // infinite loop incrementing [7]
+        let mut machine = Machine::new(&[1001, 7, 1, 7, 1105, 1, 0, 0]);
+        assert_eq!(machine.run_for(10), Ok(State::OutOfFuel));
+        assert_eq!(machine.instr_count(), 10);
+        assert_eq!(machine.run_for(11), Ok(State::OutOfFuel));
+        assert_eq!(machine.peek(7), 11);
+
+        let mut machine = Machine::new(&[104, 1, 99]);
+        assert_eq!(machine.run_for(1), Ok(State::OutOfFuel));
+        assert_eq!(machine.run_for(1), Ok(State::Halt));
+        assert_eq!(machine.run_for(1), Ok(State::Halt));
+        assert_eq!(machine.pop_output(), Some(1));
+    }
+
+    #[test]
+    fn test_fork() {
+        // [9] = in + [9]; loop
+        let prog = [3, 10, 1, 9, 10, 9, 1105, 1, 0, 100, 0];
+        let mut machine = Machine::new(&prog);
+        machine.push_input(1);
+        machine.run().unwrap();
+
+        let mut fork = machine.fork();
+        fork.push_input(10);
+        fork.run().unwrap();
+        machine.push_input(20);
+        machine.run().unwrap();
+
+        assert_eq!(fork.peek(9), 111);
+        assert_eq!(machine.peek(9), 121);
+    }
+
+    #[test]
+    fn test_self_modifying() {
+        // OUT #1; [1] += 1; loop while [1] < 4, then turn the OUT into HLT
+        let prog = [104, 1, 1001, 1, 1, 1, 1007, 1, 4, 20, 1005, 20, 0, 1101, 0, 99, 0, 1105, 1, 0];
+        let mut machine = Machine::new(&prog);
+        assert_eq!(machine.run_for(100), Ok(State::Halt));
+        assert_eq!(machine.drain_output().collect::<Vec<_>>(), [1, 2, 3]);
+
+        let mut uncached = Machine::new(&prog);
+        uncached.set_decode_cache(0);
+        assert_eq!(uncached.run_for(100), Ok(State::Halt));
+        assert_eq!(machine.instr_count(), uncached.instr_count());
+
+        // patching memory directly also invalidates the cache
+        let mut machine = Machine::new(&[104, 1, 99]);
+        machine.step().unwrap();
+        machine.poke(1, 7);
+        machine.set_ip(0).unwrap();
+        machine.step().unwrap();
+        assert_eq!(machine.drain_output().collect::<Vec<_>>(), [1, 7]);
+    }
+
+    #[test]
+    fn test_error() {
+        let mut machine = Machine::new(&[1101, 1, 2, 5, 42, 0]);
+        let err = machine.run().unwrap_err();
+        assert_eq!(err, IntcodeError::UnknownOpcode { ip: 4, instr: 42, opcode: 42 });
+        assert_eq!(err.to_string(), "unknown opcode 42 (instruction 42 at ip 4)");
+    }
+
+    #[test]
+    fn test_fault_unknown_opcode_resume() {
+        // 42 is garbage: skip it and resume
+        let mut machine = Machine::new(&[104, 1, 42, 104, 2, 99]);
+        let err = machine.run().unwrap_err();
+        assert_eq!(err, IntcodeError::UnknownOpcode { ip: 2, instr: 42, opcode: 42 });
+        assert_eq!(machine.ip(), 2);
+        machine.set_ip(3).unwrap();
+        assert!(machine.set_ip(usize::MAX).is_err());
+        assert_eq!(machine.ip(), 3);
+        assert_eq!(machine.run(), Ok(State::Halt));
+        assert_eq!(machine.drain_output().collect::<Vec<_>>(), [1, 2]);
+    }
+
+    #[test]
+    fn test_fault_invalid_mode() {
+        let mut machine = Machine::new(&[1301, 0, 0, 0, 99]);
+        assert_eq!(machine.run(), Err(IntcodeError::InvalidMode { ip: 0, instr: 1301, mode: 3 }));
+        machine.poke(0, 1101);
+        assert_eq!(machine.run(), Ok(State::Halt));
+        assert_eq!(machine.peek(0), 0);
+    }
+
+    #[test]
+    fn test_fault_unused_mode() {
+        // output only has 1 parameter, the mode digits of the rest must be 0
+        let mut machine = Machine::new(&[1104, 7, 99]);
+        assert_eq!(machine.run(), Err(IntcodeError::InvalidMode { ip: 0, instr: 1104, mode: 1 }));
+        assert_eq!(machine.output_len(), 0);
+
+        let mut machine = Machine::new(&[199]);
+        assert_eq!(machine.run(), Err(IntcodeError::InvalidMode { ip: 0, instr: 199, mode: 1 }));
+        assert!(!machine.is_halted());
+    }
+
+    #[test]
+    fn test_fault_negative_address() {
+        let mut machine = Machine::new(&[1, -5, 0, 0, 99]);
+        assert_eq!(machine.run(), Err(IntcodeError::NegativeAddress { ip: 0, instr: 1, addr: -5 }));
+
+        let mut machine = Machine::new(&[1101, 1, 1, -1, 99]);
+        assert_eq!(machine.run(), Err(IntcodeError::NegativeAddress { ip: 0, instr: 1101, addr: -1 }));
+
+        let mut machine = Machine::new(&[109, 2, 204, -3, 99]);
+        assert_eq!(machine.run(), Err(IntcodeError::NegativeAddress { ip: 2, instr: 204, addr: -1 }));
+        assert_eq!(machine.rel_base(), 2);
+
+        let mut machine = Machine::new(&[1105, 1, -7, 99]);
+        assert_eq!(machine.run(), Err(IntcodeError::NegativeAddress { ip: 0, instr: 1105, addr: -7 }));
+        assert_eq!(machine.ip(), 0);
+    }
+
+    #[test]
+    fn test_fault_write_to_immediate() {
+        let mut machine = Machine::new(&[11101, 1, 1, 0, 99]);
+        let err = IntcodeError::WriteToImmediate { ip: 0, instr: 11101, param: 3 };
+        assert_eq!(machine.run(), Err(err));
+        assert_eq!(machine.peek(3), 0);
+
+        // the input value must not be consumed by the faulting read
+        let mut machine = Machine::new(&[103, 0, 99]);
+        machine.push_input(5);
+        let err = IntcodeError::WriteToImmediate { ip: 0, instr: 103, param: 1 };
+        assert_eq!(machine.run(), Err(err));
+        machine.poke(0, 3);
+        assert_eq!(machine.run(), Ok(State::Halt));
+        assert_eq!(machine.peek(0), 5);
+    }
+
+    #[test]
+    fn test_fault_input_exhausted() {
+        let mut machine = Machine::new(&[3, 10, 3, 11, 99]);
+        machine.push_input(1);
+        assert_eq!(machine.run_to_halt(), Err(IntcodeError::InputExhausted { ip: 2, instr: 3 }));
+        machine.push_input(2);
+        assert_eq!(machine.run_to_halt(), Ok(()));
+        assert_eq!((machine.peek(10), machine.peek(11)), (1, 2));
+    }
+
+    #[test]
+    fn test_fault_overflow() {
+        let mut machine = Machine::new(&[1101, i64::MAX, 1, 0, 99]);
+        let err = IntcodeError::Overflow { ip: 0, instr: 1101, lhs: i64::MAX, rhs: 1 };
+        assert_eq!(machine.run(), Err(err));
+        assert_eq!(machine.peek(0), 1101);
+
+        let mut machine = Machine::new(&[1102, i64::MIN, -1, 0, 99]);
+        let err = IntcodeError::Overflow { ip: 0, instr: 1102, lhs: i64::MIN, rhs: -1 };
+        assert_eq!(machine.run(), Err(err));
+
+        let mut machine = Machine::new(&[109, i64::MAX, 109, 1, 99]);
+        let err = IntcodeError::Overflow { ip: 2, instr: 109, lhs: i64::MAX, rhs: 1 };
+        assert_eq!(machine.run(), Err(err));
+        assert_eq!(machine.rel_base(), i64::MAX);
+    }
+
+    #[test]
+    fn test_arithmetic_policy() {
+        let prog = [1101, i64::MAX, 1, 13, 1102, i64::MIN, -1, 14, 4, 13, 4, 14, 99, 0, 0];
+        let run_with = |arithmetic| {
+            let mut machine = Machine::new(&prog);
+            machine.set_arithmetic(arithmetic);
+            machine.run().map(|_| machine.drain_output().collect::<Vec<_>>())
+        };
+
+        assert!(matches!(run_with(Arithmetic::Checked), Err(IntcodeError::Overflow { .. })));
+        assert_eq!(run_with(Arithmetic::Wrapping), Ok(vec![i64::MIN, i64::MIN]));
+        assert_eq!(run_with(Arithmetic::Saturating), Ok(vec![i64::MAX, i64::MAX]));
+
+        // rel_base is checked anyway
+        let mut machine = Machine::new(&[109, i64::MAX, 109, 1, 99]);
+        machine.set_arithmetic(Arithmetic::Wrapping);
+        assert!(matches!(machine.run(), Err(IntcodeError::Overflow { ip: 2, .. })));
+    }
+}
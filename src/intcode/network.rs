@@ -0,0 +1,576 @@
+//! Run several machines connected to each other, scheduling them round-robin.
+//!
+//! Machines can be wired as a directed graph, where every output value of a
+//! machine is pushed to the input of all the machines it's connected to (so
+//! chains, rings and broadcasts are just different graphs), or as a packet
+//! network, where machines send packets of an address followed by a fixed
+//! number of values, and the address is the index of the destination machine.
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt::{self, Write};
+use std::sync::Mutex;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+use super::{DenseMemory, IntcodeError, IntcodeInput, IntcodeOutput, Machine, Memory, State};
+
+/// Max number of instructions that a machine runs in its turn, so a machine
+/// that doesn't do I/O can't starve the others
+const DEFAULT_SLICE: u64 = 10_000;
+/// How often the blocked threads of `run_threaded` check for a deadlock
+const DEADLOCK_POLL: Duration = Duration::from_millis(1);
+
+pub struct Network<M = DenseMemory> {
+    nodes: Vec<Node<M>>,
+    routing: Routing,
+    slice: u64,
+    /// Packets sent to addresses outside the network
+    external: VecDeque<Packet>,
+}
+
+enum Routing {
+    Edges,
+    Packets { payload_len: usize, idle_input: Option<i64> },
+}
+
+struct Node<M> {
+    machine: Machine<M>,
+    targets: Vec<usize>,
+    /// Output values of the packet being sent
+    partial_packet: Vec<i64>,
+    stats: NodeStats,
+}
+
+/// A packet sent by machine `src` to `dest`, outside the network
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+    pub src: usize,
+    pub dest: i64,
+    pub payload: Vec<i64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct NodeStats {
+    pub instructions: u64,
+    /// Values delivered to the machine by other machines
+    pub received: u64,
+    /// Values output by the machine
+    pub sent: u64,
+    /// Times that the machine read the idle input of a packet network
+    pub idle_reads: u64,
+    /// Turns that the machine ended waiting for input
+    pub waits: u64,
+}
+
+/// Why `Network::run` returned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetState {
+    /// All the machines halted
+    Halted,
+    /// All the running machines are waiting for input and no values are in
+    /// flight, so they would wait forever. In a packet network with idle
+    /// input, this means that they are only reading the idle input.
+    Deadlock,
+    /// There are packets sent outside the network, get them with
+    /// `pop_external`
+    External,
+}
+
+/// A machine of the network faulted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NetworkError {
+    pub node: usize,
+    pub error: IntcodeError,
+}
+
+impl<M: Memory> Network<M> {
+    /// Network where machines are connected with `connect`
+    pub fn new() -> Network<M> {
+        Network::with_routing(Routing::Edges)
+    }
+
+    /// Packet network, where machine N has address N. If `idle_input` is
+    /// given, it's read by the machines waiting for input with their input
+    /// queue empty, instead of blocking.
+    pub fn with_packets(payload_len: usize, idle_input: Option<i64>) -> Network<M> {
+        Network::with_routing(Routing::Packets { payload_len, idle_input })
+    }
+
+    fn with_routing(routing: Routing) -> Network<M> {
+        Network { nodes: Vec::new(), routing, slice: DEFAULT_SLICE, external: VecDeque::new() }
+    }
+
+    /// Each machine outputs to the next one
+    pub fn chain(machines: impl IntoIterator<Item = Machine<M>>) -> Network<M> {
+        let mut net = Network::new();
+        for machine in machines {
+            let id = net.add(machine);
+            if id > 0 {
+                net.connect(id - 1, id);
+            }
+        }
+        net
+    }
+
+    /// Like `chain`, and the last machine outputs to the first one
+    pub fn ring(machines: impl IntoIterator<Item = Machine<M>>) -> Network<M> {
+        let mut net = Network::chain(machines);
+        if !net.nodes.is_empty() {
+            net.connect(net.nodes.len() - 1, 0);
+        }
+        net
+    }
+
+    /// Add a machine, returning its index
+    pub fn add(&mut self, machine: Machine<M>) -> usize {
+        self.nodes.push(Node { machine, targets: Vec::new(), partial_packet: Vec::new(), stats: NodeStats::default() });
+        self.nodes.len() - 1
+    }
+
+    /// Send the output of machine `from` to the input of machine `to`. The
+    /// output of machines not connected to any other stays in their output
+    /// queue. Has no effect in packet networks.
+    pub fn connect(&mut self, from: usize, to: usize) {
+        for id in [from, to] {
+            assert!(id < self.nodes.len(), "no machine {} in the network", id);
+        }
+        self.nodes[from].targets.push(to);
+    }
+
+    /// Max number of instructions that a machine runs in its turn
+    pub fn set_slice(&mut self, slice: u64) {
+        self.slice = slice.max(1);
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn machine(&self, id: usize) -> &Machine<M> {
+        &self.nodes[id].machine
+    }
+
+    pub fn machine_mut(&mut self, id: usize) -> &mut Machine<M> {
+        &mut self.nodes[id].machine
+    }
+
+    pub fn push_input(&mut self, id: usize, val: i64) {
+        self.nodes[id].machine.push_input(val);
+    }
+
+    pub fn stats(&self, id: usize) -> &NodeStats {
+        &self.nodes[id].stats
+    }
+
+    pub fn pop_external(&mut self) -> Option<Packet> {
+        self.external.pop_front()
+    }
+
+    /// Run the machines by turns until all of them halt, they deadlock or a
+    /// packet is sent outside the network. Call `run` again to resume.
+    pub fn run(&mut self) -> Result<NetState, NetworkError> {
+        loop {
+            let mut all_halted = true;
+            let mut all_waiting = true;
+            let mut sent = false;
+
+            for id in 0..self.nodes.len() {
+                if self.nodes[id].machine.is_halted() {
+                    continue;
+                }
+                all_halted = false;
+
+                let (state, n_sent) = self.turn(id)?;
+                all_waiting &= state == State::WaitInput;
+                sent |= n_sent > 0;
+                self.route(id);
+            }
+
+            if all_halted {
+                return Ok(NetState::Halted);
+            }
+            if !self.external.is_empty() {
+                return Ok(NetState::External);
+            }
+            if all_waiting && !sent {
+                return Ok(NetState::Deadlock);
+            }
+        }
+    }
+
+    /// Run a machine for a slice, returning how it stopped and the number of
+    /// values that it output
+    fn turn(&mut self, id: usize) -> Result<(State, usize), NetworkError> {
+        let idle_input = match self.routing {
+            Routing::Packets { idle_input, .. } => idle_input,
+            Routing::Edges => None,
+        };
+        let node = &mut self.nodes[id];
+        let (count, output_len) = (node.machine.instr_count(), node.machine.output_len());
+        let mut idle_read = false;
+
+        let state = loop {
+            let state = node.machine.run_for(self.slice)
+                .map_err(|error| NetworkError { node: id, error })?;
+            match (state, idle_input) {
+                (State::WaitInput, Some(idle)) if !idle_read => {
+                    node.machine.push_input(idle);
+                    node.stats.idle_reads += 1;
+                    idle_read = true;
+                },
+                _ => break state,
+            }
+        };
+
+        let n_sent = node.machine.output_len() - output_len;
+        node.stats.instructions += node.machine.instr_count() - count;
+        node.stats.sent += n_sent as u64;
+        node.stats.waits += (state == State::WaitInput) as u64;
+        Ok((state, n_sent))
+    }
+
+    /// Send the output values of a machine to their destinations
+    fn route(&mut self, id: usize) {
+        match self.routing {
+            Routing::Edges if self.nodes[id].targets.is_empty() => (),
+            Routing::Edges => {
+                let outputs: Vec<i64> = self.nodes[id].machine.drain_output().collect();
+                for target in self.nodes[id].targets.clone() {
+                    self.deliver(target, &outputs);
+                }
+            },
+            Routing::Packets { payload_len, .. } => {
+                let outputs: Vec<i64> = self.nodes[id].machine.drain_output().collect();
+                for val in outputs {
+                    let packet = &mut self.nodes[id].partial_packet;
+                    packet.push(val);
+                    if packet.len() < 1 + payload_len {
+                        continue;
+                    }
+                    let payload = packet.split_off(1);
+                    let dest = packet.pop().unwrap();
+                    match usize::try_from(dest) {
+                        Ok(target) if target < self.nodes.len() => self.deliver(target, &payload),
+                        _ => self.external.push_back(Packet { src: id, dest, payload }),
+                    }
+                }
+            },
+        }
+    }
+
+    fn deliver(&mut self, id: usize, vals: &[i64]) {
+        let node = &mut self.nodes[id];
+        node.machine.extend_input(vals.iter().copied());
+        node.stats.received += vals.len() as u64;
+    }
+
+    /// Table with the statistics of every machine
+    pub fn report(&self) -> String {
+        let mut report = format!("{:>4} {:>12} {:>10} {:>10} {:>10} {:>10}  state\n",
+                                 "node", "instructions", "received", "sent", "idle reads", "waits");
+        for (id, node) in self.nodes.iter().enumerate() {
+            let s = &node.stats;
+            let state = if node.machine.is_halted() { "halted" } else { "running" };
+            writeln!(report, "{:>4} {:>12} {:>10} {:>10} {:>10} {:>10}  {}",
+                     id, s.instructions, s.received, s.sent, s.idle_reads, s.waits, state).unwrap();
+        }
+        report
+    }
+}
+
+impl<M: Memory> Network<M> {
+    /// Like `run`, but run every machine on its own thread until it halts,
+    /// connected with channels. A machine waiting for input blocks its thread
+    /// instead of giving the turn to the next one.
+    ///
+    /// When a machine halts, its thread ends and the channels to the
+    /// machines connected to it are closed. The machines that can't receive
+    /// input anymore, because all the machines sending to them ended or
+    /// because all of them are waiting for each other, end too, and the
+    /// result is `NetState::Deadlock`. Values sent to a machine after it
+    /// ended are left in its input queue. Packet networks are not supported.
+    pub fn run_threaded(&mut self) -> Result<NetState, NetworkError> {
+        assert!(matches!(self.routing, Routing::Edges), "run_threaded doesn't support packet networks");
+
+        let n = self.nodes.len();
+        let (senders, mut receivers): (Vec<Sender<i64>>, Vec<Receiver<i64>>) = (0..n).map(|_| mpsc::channel()).unzip();
+        let blocking = Mutex::new(Blocking {
+            alive: self.nodes.iter().map(|node| !node.machine.is_halted()).collect(),
+            waiting: vec![false; n],
+            pending: vec![0; n],
+            received: vec![0; n],
+            deadlock: false,
+        });
+
+        // the receivers are moved to the threads and returned when they end,
+        // so the values sent to machines that already ended aren't lost
+        let mut results = Vec::new();
+        thread::scope(|scope| {
+            let mut handles = Vec::new();
+            for (id, node) in self.nodes.iter_mut().enumerate() {
+                if node.machine.is_halted() {
+                    continue;
+                }
+                let (_, rx) = mpsc::channel();
+                let rx = std::mem::replace(&mut receivers[id], rx);
+                let targets = node.targets.iter().map(|&t| (t, senders[t].clone())).collect();
+                let blocking = &blocking;
+                let handle = scope.spawn(move || {
+                    let mut input = ChannelInput { id, rx, blocking };
+                    let mut output = ChannelOutput { targets, blocking, unrouted: Vec::new(), sent: 0 };
+                    let count = node.machine.instr_count();
+                    let result = node.machine.run_io(&mut input, &mut output);
+                    blocking.lock().unwrap().end(id);
+
+                    node.machine.output_mut().extend(output.unrouted);
+                    node.stats.instructions += node.machine.instr_count() - count;
+                    node.stats.sent += output.sent;
+                    node.stats.waits += (result == Ok(State::WaitInput)) as u64;
+                    (result, input.rx)
+                });
+                handles.push((id, handle));
+            }
+            drop(senders);
+
+            for (id, handle) in handles {
+                let (result, rx) = handle.join().unwrap();
+                receivers[id] = rx;
+                results.push(result.map_err(|error| NetworkError { node: id, error }));
+            }
+        });
+
+        let blocking = blocking.into_inner().unwrap();
+        for (node, (rx, received)) in self.nodes.iter_mut().zip(receivers.iter().zip(blocking.received)) {
+            node.machine.extend_input(rx.try_iter());
+            node.stats.received += received;
+        }
+
+        for result in results {
+            result?;
+        }
+        match self.nodes.iter().all(|node| node.machine.is_halted()) {
+            true => Ok(NetState::Halted),
+            false => Ok(NetState::Deadlock),
+        }
+    }
+}
+
+/// State of the threads of `run_threaded`, to detect when all of them are
+/// waiting for each other
+struct Blocking {
+    /// Whether the thread of each machine is still running
+    alive: Vec<bool>,
+    /// Whether each machine is blocked waiting for input
+    waiting: Vec<bool>,
+    /// Values sent to each machine and not read yet
+    pending: Vec<usize>,
+    received: Vec<u64>,
+    deadlock: bool,
+}
+
+impl Blocking {
+    fn check_deadlock(&mut self) {
+        let live = (0..self.alive.len()).filter(|&id| self.alive[id]);
+        self.deadlock |= live.clone().count() > 0
+            && live.clone().all(|id| self.waiting[id] && self.pending[id] == 0);
+    }
+
+    fn end(&mut self, id: usize) {
+        self.alive[id] = false;
+        self.waiting[id] = false;
+        self.check_deadlock();
+    }
+
+    fn read(&mut self, id: usize) {
+        self.waiting[id] = false;
+        self.pending[id] = self.pending[id].saturating_sub(1);
+    }
+}
+
+struct ChannelInput<'a> {
+    id: usize,
+    rx: Receiver<i64>,
+    blocking: &'a Mutex<Blocking>,
+}
+
+impl IntcodeInput for ChannelInput<'_> {
+    fn read(&mut self) -> Option<i64> {
+        if let Ok(val) = self.rx.try_recv() {
+            self.blocking.lock().unwrap().read(self.id);
+            return Some(val);
+        }
+
+        {
+            let mut blocking = self.blocking.lock().unwrap();
+            blocking.waiting[self.id] = true;
+            blocking.check_deadlock();
+        }
+        loop {
+            match self.rx.recv_timeout(DEADLOCK_POLL) {
+                Ok(val) => {
+                    self.blocking.lock().unwrap().read(self.id);
+                    return Some(val);
+                },
+                Err(RecvTimeoutError::Timeout) if !self.blocking.lock().unwrap().deadlock => (),
+                Err(_) => return None,
+            }
+        }
+    }
+}
+
+struct ChannelOutput<'a> {
+    targets: Vec<(usize, Sender<i64>)>,
+    blocking: &'a Mutex<Blocking>,
+    /// Output of a machine not connected to any other
+    unrouted: Vec<i64>,
+    sent: u64,
+}
+
+impl IntcodeOutput for ChannelOutput<'_> {
+    fn write(&mut self, val: i64) {
+        self.sent += 1;
+        if self.targets.is_empty() {
+            self.unrouted.push(val);
+        }
+        for (target, tx) in &self.targets {
+            {
+                let mut blocking = self.blocking.lock().unwrap();
+                blocking.received[*target] += 1;
+                if blocking.alive[*target] {
+                    blocking.pending[*target] += 1;
+                }
+            }
+            // the receivers outlive the threads, so this can't fail
+            tx.send(val).unwrap();
+        }
+    }
+}
+
+impl<M: Memory> Default for Network<M> {
+    fn default() -> Self {
+        Network::new()
+    }
+}
+
+impl fmt::Display for NetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "machine {}: {}", self.node, self.error)
+    }
+}
+
+impl Error for NetworkError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::asm::assemble;
+
+    // out in + 1, until it's 10 or more
+    const INC: &str = "
+        loop:   IN [x]
+                ADD [x], #1, [x]
+                OUT [x]
+                LT [x], #10, [c]
+                JT [c], #loop
+                HLT
+        x:      DB 0
+        c:      DB 0
+    ";
+
+    fn machines(src: &str, n: usize) -> Vec<Machine> {
+        let prog = assemble(src).unwrap();
+        (0..n).map(|_| Machine::new(&prog)).collect()
+    }
+
+    #[test]
+    fn test_ring_and_deadlock() {
+        let mut net = Network::ring(machines(INC, 3));
+        net.push_input(0, 0);
+        assert_eq!(net.run(), Ok(NetState::Halted));
+        assert_eq!(net.machine(0).input().iter().copied().collect::<Vec<_>>(), [12]);
+        assert_eq!(net.stats(1).received, 4);
+        assert_eq!(net.stats(1).sent, 4);
+
+        let mut net = Network::chain(machines(INC, 2));
+        assert_eq!(net.run(), Ok(NetState::Deadlock));
+
+        // broadcast, the output of the sinks stays in their output queues
+        let mut net = Network::new();
+        let src = net.add(Machine::new(&[104, 5, 99]));
+        for sink in machines(INC, 2) {
+            let id = net.add(sink);
+            net.connect(src, id);
+        }
+        assert_eq!(net.run(), Ok(NetState::Deadlock));
+        assert_eq!(net.machine(1).output().front(), Some(&6));
+        assert_eq!(net.machine(2).output().front(), Some(&6));
+    }
+
+    #[test]
+    fn test_run_threaded() {
+        let mut net = Network::ring(machines(INC, 3));
+        net.push_input(0, 0);
+        assert_eq!(net.run_threaded(), Ok(NetState::Halted));
+        assert_eq!(net.machine(0).input().iter().copied().collect::<Vec<_>>(), [12]);
+        assert_eq!(net.stats(1).received, 4);
+        assert_eq!(net.stats(2).instructions, net.machine(2).instr_count());
+
+        // all waiting for each other
+        let mut net = Network::ring(machines(INC, 3));
+        assert_eq!(net.run_threaded(), Ok(NetState::Deadlock));
+
+        // the sinks block after the source halts and closes their input
+        let mut net = Network::new();
+        let src = net.add(Machine::new(&[104, 5, 99]));
+        for sink in machines(INC, 2) {
+            let id = net.add(sink);
+            net.connect(src, id);
+        }
+        assert_eq!(net.run_threaded(), Ok(NetState::Deadlock));
+        assert_eq!(net.machine(1).output().front(), Some(&6));
+        assert_eq!(net.stats(2).waits, 1);
+
+        let mut net = Network::chain([Machine::new(&[104, 1, 99]), Machine::new(&[3, 0, 1, 0, 0, 0, 42])]);
+        let err = net.run_threaded().unwrap_err();
+        assert_eq!(err.node, 1);
+        assert!(matches!(err.error, IntcodeError::UnknownOpcode { ip: 6, .. }));
+    }
+
+    #[test]
+    fn test_packets() {
+        let receiver = assemble("
+            loop:   IN [x]
+                    EQ [x], #-1, [c]
+                    JT [c], #loop
+                    OUT #255
+                    OUT [x]
+                    HLT
+            x:      DB 0
+            c:      DB 0
+        ").unwrap();
+        let sender = assemble("
+                    OUT #0
+                    OUT #42
+                    OUT #255
+                    OUT #7
+                    HLT
+        ").unwrap();
+
+        let mut net = Network::with_packets(1, Some(-1));
+        net.add(Machine::new(&receiver));
+        net.add(Machine::new(&sender));
+
+        assert_eq!(net.run(), Ok(NetState::External));
+        assert_eq!(net.pop_external(), Some(Packet { src: 1, dest: 255, payload: vec![7] }));
+        assert_eq!(net.pop_external(), None);
+        assert_eq!(net.run(), Ok(NetState::External));
+        assert_eq!(net.pop_external(), Some(Packet { src: 0, dest: 255, payload: vec![42] }));
+        assert_eq!(net.run(), Ok(NetState::Halted));
+        assert_eq!(net.stats(0).idle_reads, 1);
+    }
+}
@@ -0,0 +1,198 @@
+//! Execution profiler, implemented as a `Tracer`: counts how many times each
+//! instruction and opcode is executed, detects loops from the jumps taken
+//! backwards and counts the memory reads and writes done by the operands.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use itertools::Itertools;
+use super::{Instr, Mode, Opcode};
+use super::trace::{TraceRecord, Tracer};
+
+/// Size of the memory regions in the heatmap of the report
+const HEATMAP_REGION: usize = 64;
+/// Width of the longest bar in the heatmap of the report
+const HEATMAP_WIDTH: usize = 40;
+
+#[derive(Default)]
+pub struct Profiler {
+    total: u64,
+    /// Hits of each executed address, with the last instruction seen there
+    hits: HashMap<usize, (u64, Instr)>,
+    opcodes: HashMap<Opcode, u64>,
+    /// Times that each back-edge was taken, by (head, tail) address
+    back_edges: HashMap<(usize, usize), u64>,
+    reads: BTreeMap<usize, u64>,
+    writes: BTreeMap<usize, u64>,
+    /// Address and size of the previous instruction
+    last: Option<(usize, usize)>,
+}
+
+/// A loop found from a back-edge: a jump at `tail` taken backwards to `head`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Loop {
+    pub head: usize,
+    pub tail: usize,
+    pub iterations: u64,
+    /// Instructions executed in the `head..=tail` range. Code called from the
+    /// loop body and located elsewhere is not included.
+    pub instructions: u64,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler::default()
+    }
+
+    /// Number of instructions profiled
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    pub fn hits(&self, addr: usize) -> u64 {
+        self.hits.get(&addr).map_or(0, |&(hits, _)| hits)
+    }
+
+    pub fn opcode_count(&self, opcode: Opcode) -> u64 {
+        self.opcodes.get(&opcode).copied().unwrap_or(0)
+    }
+
+    pub fn reads(&self, addr: usize) -> u64 {
+        self.reads.get(&addr).copied().unwrap_or(0)
+    }
+
+    pub fn writes(&self, addr: usize) -> u64 {
+        self.writes.get(&addr).copied().unwrap_or(0)
+    }
+
+    /// Loops found, sorted by the number of instructions executed in them
+    pub fn loops(&self) -> Vec<Loop> {
+        self.back_edges.iter()
+            .map(|(&(head, tail), &iterations)| Loop {
+                head,
+                tail,
+                iterations,
+                instructions: (head..=tail).map(|addr| self.hits(addr)).sum(),
+            })
+            .sorted_by_key(|l| (std::cmp::Reverse(l.instructions), l.head, l.tail))
+            .collect()
+    }
+
+    /// Report with the opcode counts, the hottest instructions and loops
+    /// (at most `top` of each) and a heatmap of memory accesses
+    pub fn report(&self, top: usize) -> String {
+        let mut report = String::new();
+        let pct = |n: u64| 100.0 * n as f64 / self.total.max(1) as f64;
+        writeln!(report, "Instructions executed: {}", self.total).unwrap();
+
+        writeln!(report, "\nOpcodes:").unwrap();
+        for (opcode, count) in self.opcodes.iter().sorted_by_key(|(op, &n)| (std::cmp::Reverse(n), op.code())) {
+            writeln!(report, "  {:<4} {:>12} {:>6.2}%", opcode.mnemonic(), count, pct(*count)).unwrap();
+        }
+
+        writeln!(report, "\nHot instructions:").unwrap();
+        let hot = self.hits.iter().sorted_by_key(|(&addr, &(n, _))| (std::cmp::Reverse(n), addr));
+        for (addr, (hits, instr)) in hot.take(top) {
+            writeln!(report, "  {:>6} {:>12} {:>6.2}%  {}", addr, hits, pct(*hits), instr).unwrap();
+        }
+
+        writeln!(report, "\nLoops:").unwrap();
+        for l in self.loops().iter().take(top) {
+            let range = format!("{}-{}", l.head, l.tail);
+            writeln!(report, "  {:<13} {:>10} iterations {:>12} instructions {:>6.2}%",
+                     range, l.iterations, l.instructions, pct(l.instructions)).unwrap();
+        }
+
+        writeln!(report, "\nMemory accesses (reads/writes every {} cells):", HEATMAP_REGION).unwrap();
+        let mut regions: BTreeMap<usize, (u64, u64)> = BTreeMap::new();
+        for (addr, n) in &self.reads {
+            regions.entry(addr / HEATMAP_REGION).or_default().0 += n;
+        }
+        for (addr, n) in &self.writes {
+            regions.entry(addr / HEATMAP_REGION).or_default().1 += n;
+        }
+        let max = regions.values().map(|(r, w)| r + w).max().unwrap_or(1);
+        for (region, (reads, writes)) in regions {
+            let bar = ((reads + writes) as f64 / max as f64 * HEATMAP_WIDTH as f64).ceil() as usize;
+            writeln!(report, "  {:>6} {:>12} {:>12}  {}",
+                     region * HEATMAP_REGION, reads, writes, "#".repeat(bar)).unwrap();
+        }
+
+        report
+    }
+
+    /// Hits in the folded stacks format used by flamegraph tools: one line
+    /// per instruction, with the loops that contain it as the stack frames,
+    /// outermost first.
+    pub fn folded(&self) -> String {
+        let loops = self.loops().into_iter()
+            .sorted_by_key(|l| (std::cmp::Reverse(l.tail - l.head), l.head))
+            .collect_vec();
+
+        let mut folded = String::new();
+        for (addr, (hits, instr)) in self.hits.iter().sorted_by_key(|(&addr, _)| addr) {
+            let frames = loops.iter()
+                .filter(|l| (l.head..=l.tail).contains(addr))
+                .map(|l| format!("L{}-{}", l.head, l.tail));
+            let stack = std::iter::once("prog".to_string())
+                .chain(frames)
+                .chain(std::iter::once(format!("{}:{}", addr, instr.opcode.mnemonic())))
+                .join(";");
+            writeln!(folded, "{} {}", stack, hits).unwrap();
+        }
+        folded
+    }
+}
+
+impl Tracer for Profiler {
+    fn trace(&mut self, record: &TraceRecord) {
+        let ip = record.ip as usize;
+        self.total += 1;
+        self.hits.entry(ip).or_insert((0, record.instr)).0 += 1;
+        *self.opcodes.entry(record.instr.opcode).or_default() += 1;
+
+        // a jump is taken backwards when ip doesn't follow the previous
+        // instruction and goes to a lower address
+        if let Some((last_ip, last_size)) = self.last {
+            if ip != last_ip + last_size && ip <= last_ip {
+                *self.back_edges.entry((ip, last_ip)).or_default() += 1;
+            }
+        }
+        self.last = Some((ip, record.instr.size()));
+
+        let dest = record.instr.opcode.dest_param();
+        for (i, (param, operand)) in record.instr.params().iter().zip(record.operands()).enumerate() {
+            let addr = match (param.mode, operand.addr) {
+                (Mode::Imm, _) | (_, None) => continue,
+                (_, Some(addr)) => addr,
+            };
+            let counts = if dest == Some(i) { &mut self.writes } else { &mut self.reads };
+            *counts.entry(addr).or_default() += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::Machine;
+
+    #[test]
+    fn test_profile_loop() {
+        // count [13] down from 3 to 0, outputting it on each iteration
+        let prog = [4, 13, 1001, 13, -1, 13, 1005, 13, 0, 99, 0, 0, 0, 3];
+        let mut machine = Machine::new(&prog);
+        machine.set_tracer(Profiler::new());
+        machine.run().unwrap();
+        let prof = machine.take_tracer::<Profiler>().unwrap();
+
+        assert_eq!(prof.total(), 10);
+        assert_eq!(prof.hits(0), 3);
+        assert_eq!(prof.hits(9), 1);
+        assert_eq!(prof.opcode_count(Opcode::Add), 3);
+        assert_eq!(prof.reads(13), 9);
+        assert_eq!(prof.writes(13), 3);
+        assert_eq!(prof.loops(), vec![Loop { head: 0, tail: 6, iterations: 2, instructions: 9 }]);
+        assert_eq!(prof.folded().lines().next(), Some("prog;L0-6;0:OUT 3"));
+        assert!(prof.report(5).contains("0-6                    2 iterations"));
+    }
+}
@@ -0,0 +1,265 @@
+//! Record the I/O of a machine, every value it reads and writes with the
+//! number of instructions executed before it, to replay it later: run the
+//! program again with the recorded inputs and check that it writes the same
+//! outputs at the same points, i.e. to test changes to the VM.
+//!
+//! Replay files are text, with a session per recorded machine:
+//!
+//! ```text
+//! # comments start with a hash
+//! session
+//! 9 in 1
+//! 207 out 3100786347
+//! 209 halt
+//! ```
+//!
+//! Every session ends with how the machine stopped: `halt`, `wait` (for
+//! input, or out of fuel) or `fault`.
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use super::{IntcodeError, Machine, Memory, Opcode, State};
+use super::network::Network;
+use super::trace::{TraceRecord, Tracer};
+use super::varint::invalid_data;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    In(i64),
+    Out(i64),
+    Halt,
+    Wait,
+    Fault,
+}
+
+/// An event and the number of instructions executed before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timed {
+    pub count: u64,
+    pub event: Event,
+}
+
+/// The recorded I/O of a machine, ending with how it stopped
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Session {
+    pub events: Vec<Timed>,
+}
+
+/// First difference between a session and its replay. `None` means that
+/// there's no event at that point.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplayError {
+    pub index: usize,
+    pub expected: Option<Timed>,
+    pub found: Option<Timed>,
+}
+
+/// Tracer that records the values read and written by a machine
+#[derive(Debug, Default)]
+pub struct Recorder {
+    events: Vec<Timed>,
+}
+
+impl Recorder {
+    pub fn new() -> Recorder {
+        Recorder::default()
+    }
+
+    /// Session with the recorded events, ended by the `result` of the last
+    /// run of `machine`
+    pub fn finish<M: Memory>(mut self, machine: &Machine<M>, result: Result<State, IntcodeError>) -> Session {
+        let event = match result {
+            Ok(State::Halt) => Event::Halt,
+            Ok(_) => Event::Wait,
+            Err(_) => Event::Fault,
+        };
+        self.events.push(Timed { count: machine.instr_count(), event });
+        Session { events: self.events }
+    }
+}
+
+impl Tracer for Recorder {
+    fn trace(&mut self, record: &TraceRecord) {
+        let event = match record.instr.opcode {
+            Opcode::In => Event::In(record.operands()[0].post),
+            Opcode::Out => Event::Out(record.operands()[0].pre),
+            _ => return,
+        };
+        self.events.push(Timed { count: record.count, event });
+    }
+}
+
+impl Session {
+    /// Input values, in the order they were read
+    pub fn inputs(&self) -> impl Iterator<Item = i64> + '_ {
+        self.events.iter().filter_map(|timed| match timed.event {
+            Event::In(val) => Some(val),
+            _ => None,
+        })
+    }
+
+    /// Run `prog` with all the recorded inputs, checking that it produces
+    /// the same events
+    pub fn replay(&self, prog: &[i64]) -> Result<(), ReplayError> {
+        let mut machine = Machine::new(prog);
+        machine.extend_input(self.inputs());
+        let replayed = record(&mut machine);
+
+        let len = self.events.len().max(replayed.events.len());
+        match (0..len).find(|&i| self.events.get(i) != replayed.events.get(i)) {
+            Some(index) => Err(ReplayError {
+                index,
+                expected: self.events.get(index).copied(),
+                found: replayed.events.get(index).copied(),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Run `machine` until it stops, recording its I/O
+pub fn record<M: Memory>(machine: &mut Machine<M>) -> Session {
+    machine.set_tracer(Recorder::new());
+    let result = machine.run();
+    machine.take_tracer::<Recorder>().unwrap().finish(machine, result)
+}
+
+/// Run `network` until it stops, recording the I/O of every machine, with a
+/// session per machine in order. Machines that didn't halt or fault are
+/// recorded as waiting.
+pub fn record_network<M: Memory>(network: &mut Network<M>) -> Vec<Session> {
+    for id in 0..network.len() {
+        network.machine_mut(id).set_tracer(Recorder::new());
+    }
+    let result = network.run();
+
+    (0..network.len()).map(|id| {
+        let machine = network.machine_mut(id);
+        let state = match result {
+            Err(err) if err.node == id => Err(err.error),
+            _ if machine.is_halted() => Ok(State::Halt),
+            _ => Ok(State::WaitInput),
+        };
+        machine.take_tracer::<Recorder>().unwrap().finish(machine, state)
+    }).collect()
+}
+
+/// Write sessions in the text format of replay files
+pub fn write(sessions: &[Session], mut writer: impl Write) -> io::Result<()> {
+    for session in sessions {
+        writeln!(writer, "session")?;
+        for timed in &session.events {
+            writeln!(writer, "{}", timed)?;
+        }
+    }
+    writer.flush()
+}
+
+/// Read the sessions of a replay file
+pub fn read(reader: impl BufRead) -> io::Result<Vec<Session>> {
+    let mut sessions: Vec<Session> = Vec::new();
+
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let err = |msg: &str| invalid_data(&format!("line {}: {}", i + 1, msg));
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line == "session" {
+            sessions.push(Session::default());
+            continue;
+        }
+
+        let session = sessions.last_mut().ok_or_else(|| err("expected 'session' first"))?;
+        let mut words = line.split_whitespace();
+        let count = words.next().and_then(|w| w.parse().ok()).ok_or_else(|| err("invalid instruction count"))?;
+        let kind = words.next();
+        let mut value = || words.next().and_then(|w| w.parse().ok()).ok_or_else(|| err("invalid value"));
+        let event = match kind {
+            Some("in") => Event::In(value()?),
+            Some("out") => Event::Out(value()?),
+            Some("halt") => Event::Halt,
+            Some("wait") => Event::Wait,
+            Some("fault") => Event::Fault,
+            _ => return Err(err("unknown event")),
+        };
+        session.events.push(Timed { count, event });
+    }
+
+    Ok(sessions)
+}
+
+impl fmt::Display for Timed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.event {
+            Event::In(val) => write!(f, "{} in {}", self.count, val),
+            Event::Out(val) => write!(f, "{} out {}", self.count, val),
+            Event::Halt => write!(f, "{} halt", self.count),
+            Event::Wait => write!(f, "{} wait", self.count),
+            Event::Fault => write!(f, "{} fault", self.count),
+        }
+    }
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let show = |timed: Option<Timed>| timed.map_or("nothing".to_string(), |timed| format!("'{}'", timed));
+        write!(f, "event {}: expected {}, found {}", self.index, show(self.expected), show(self.found))
+    }
+}
+
+impl Error for ReplayError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // out in * 3, until in is 0
+    const PROG: [i64; 16] = [3, 15, 1006, 15, 14, 1002, 15, 3, 15, 4, 15, 1105, 1, 0, 99, 0];
+
+    #[test]
+    fn test_record_and_replay() {
+        let mut machine = Machine::new(&PROG);
+        machine.extend_input([4, 5]);
+        let session = record(&mut machine);
+
+        let sessions = vec![session];
+        let mut file = Vec::new();
+        write(&sessions, &mut file).unwrap();
+        assert_eq!(String::from_utf8_lossy(&file), "session\n0 in 4\n3 out 12\n5 in 5\n8 out 15\n10 wait\n");
+        assert_eq!(read(&file[..]).unwrap(), sessions);
+        let session = &sessions[0];
+        assert_eq!(session.replay(&PROG), Ok(()));
+
+        // a program that outputs in * 4
+        let mut changed = PROG;
+        changed[7] = 4;
+        let err = session.replay(&changed).unwrap_err();
+        assert_eq!(err.to_string(), "event 1: expected '3 out 12', found '3 out 16'");
+
+        assert!(read("0 in 4\n".as_bytes()).is_err());
+        assert!(read("session\n0 jump 4\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_replay_inputs() {
+        // written by `cargo run --bin intreplay record ...`, see the Readme
+        let replays = [
+            ("day05", include_str!("replay/day05.txt")),
+            ("day07", include_str!("replay/day07.txt")),
+            ("day09", include_str!("replay/day09.txt")),
+        ];
+        for (day_xx, replay) in replays {
+            let prog = crate::input::parse_tokens_split_str_unsafe::<i64>(day_xx, ",").next().unwrap();
+            let sessions = read(replay.as_bytes()).unwrap();
+            assert!(!sessions.is_empty());
+            for session in sessions {
+                if let Err(err) = session.replay(&prog) {
+                    panic!("{}: {}", day_xx, err);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,205 @@
+//! Save and load the full state of a machine: memory, registers, I/O queues
+//! and the halted flag. The tracer is not part of the state.
+//!
+//! Snapshots start with the magic bytes "ICSN" and a version byte, followed
+//! by zigzag LEB128 varints: ip, rel_base, halted, instr_count, the
+//! arithmetic policy (0 checked, 1 wrapping, 2 saturating, 3 big), the
+//! number of non-zero memory cells and their address/value pairs, the number
+//! of cells that don't fit in an i64 and their address, byte length and
+//! little-endian two's complement bytes (only with the `bignum` feature), then
+//! the length and values of the input and output queues.
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use super::{Arithmetic, Machine, Memory};
+use super::varint::{self, invalid_data};
+
+const MAGIC: &[u8; 4] = b"ICSN";
+const VERSION: u8 = 3;
+
+impl<M: Memory> Machine<M> {
+    /// Write a snapshot of the machine state that can be restored with `load_snapshot`
+    pub fn save_snapshot(&self, mut writer: impl Write) -> io::Result<()> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(MAGIC);
+        buf.push(VERSION);
+        varint::write(&mut buf, self.ip);
+        varint::write(&mut buf, self.rel_base);
+        varint::write(&mut buf, self.halted as i64);
+        varint::write(&mut buf, self.instr_count as i64);
+        let arithmetic = match self.arithmetic {
+            Arithmetic::Checked => 0,
+            Arithmetic::Wrapping => 1,
+            Arithmetic::Saturating => 2,
+            #[cfg(feature = "bignum")]
+            Arithmetic::Big => 3,
+        };
+        varint::write(&mut buf, arithmetic);
+
+        let mut cells: Vec<(usize, i64)> = self.mem.cells().collect();
+        cells.sort_unstable();
+        varint::write(&mut buf, cells.len() as i64);
+        for (addr, val) in cells {
+            varint::write(&mut buf, addr as i64);
+            varint::write(&mut buf, val);
+        }
+
+        #[cfg(feature = "bignum")]
+        {
+            varint::write(&mut buf, self.big.len() as i64);
+            for (&addr, val) in &self.big {
+                let bytes = val.to_signed_bytes_le();
+                varint::write(&mut buf, addr as i64);
+                varint::write(&mut buf, bytes.len() as i64);
+                buf.extend_from_slice(&bytes);
+            }
+        }
+        #[cfg(not(feature = "bignum"))]
+        varint::write(&mut buf, 0);
+
+        for queue in [&self.input, &self.output] {
+            varint::write(&mut buf, queue.len() as i64);
+            for &val in queue {
+                varint::write(&mut buf, val);
+            }
+        }
+
+        writer.write_all(&buf)?;
+        writer.flush()
+    }
+
+    /// Restore a machine from a snapshot written by `save_snapshot`
+    pub fn load_snapshot(mut reader: impl Read) -> io::Result<Machine<M>> {
+        let mut header = [0; 5];
+        reader.read_exact(&mut header)?;
+        if &header[..4] != MAGIC || header[4] != VERSION {
+            return Err(invalid_data("not an Intcode snapshot, or unsupported version"));
+        }
+
+        let mut machine = Machine::<M>::with_memory(&[]);
+        machine.ip = varint::read_more(&mut reader)?;
+        machine.rel_base = varint::read_more(&mut reader)?;
+        machine.halted = varint::read_more(&mut reader)? != 0;
+        machine.instr_count = varint::read_more(&mut reader)? as u64;
+        machine.arithmetic = match varint::read_more(&mut reader)? {
+            0 => Arithmetic::Checked,
+            1 => Arithmetic::Wrapping,
+            2 => Arithmetic::Saturating,
+            #[cfg(feature = "bignum")]
+            3 => Arithmetic::Big,
+            _ => return Err(invalid_data("unknown arithmetic policy")),
+        };
+
+        for _ in 0..read_len(&mut reader)? {
+            let addr = usize::try_from(varint::read_more(&mut reader)?)
+                .map_err(|_| invalid_data("negative address"))?;
+            machine.mem.set(addr, varint::read_more(&mut reader)?);
+        }
+
+        let big_cells = read_big_cells(&mut reader)?;
+        #[cfg(feature = "bignum")]
+        machine.big.extend(big_cells.into_iter().map(|(addr, bytes)| {
+            (addr, num_bigint::BigInt::from_signed_bytes_le(&bytes))
+        }));
+        #[cfg(not(feature = "bignum"))]
+        if let Some((addr, _)) = big_cells.first() {
+            return Err(invalid_data(&format!("value at address {} needs the bignum feature", addr)));
+        }
+
+        let code_len = machine.mem.cells().map(|(addr, _)| addr.saturating_add(1)).max().unwrap_or(0);
+        machine.set_decode_cache(code_len);
+
+        machine.input = read_queue(&mut reader)?;
+        machine.output = read_queue(&mut reader)?;
+        Ok(machine)
+    }
+}
+
+fn read_len(reader: &mut impl Read) -> io::Result<usize> {
+    usize::try_from(varint::read_more(reader)?).map_err(|_| invalid_data("negative length"))
+}
+
+/// Addresses and two's complement bytes of the values that don't fit in an i64
+fn read_big_cells(reader: &mut impl Read) -> io::Result<Vec<(usize, Vec<u8>)>> {
+    let len = read_len(reader)?;
+    (0..len).map(|_| {
+        let addr = usize::try_from(varint::read_more(reader)?).map_err(|_| invalid_data("negative address"))?;
+        let len = read_len(reader)?;
+        let mut bytes = Vec::new();
+        if reader.take(len as u64).read_to_end(&mut bytes)? < len {
+            return Err(invalid_data("truncated data"));
+        }
+        Ok((addr, bytes))
+    }).collect()
+}
+
+fn read_queue(reader: &mut impl Read) -> io::Result<VecDeque<i64>> {
+    let len = read_len(reader)?;
+    (0..len).map(|_| varint::read_more(reader)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{DenseMemory, State};
+
+    #[test]
+    fn test_snapshot_roundtrip() {
+        // out in * 3, twice
+        let prog = [3, 13, 1002, 13, 3, 13, 4, 13, 1105, 1, 0, 99, 0, 0];
+        let mut machine = Machine::new(&prog);
+        machine.extend_input([5, 7]);
+        machine.run_for(7).unwrap();
+        machine.push_input(9);
+
+        let mut snapshot = Vec::new();
+        machine.save_snapshot(&mut snapshot).unwrap();
+        let mut restored: Machine = Machine::load_snapshot(snapshot.as_slice()).unwrap();
+
+        assert_eq!(restored.ip(), machine.ip());
+        assert_eq!(restored.instr_count(), 7);
+        assert_eq!(restored.input(), machine.input());
+        assert_eq!(restored.output(), machine.output());
+        assert_eq!(restored.run(), Ok(State::WaitInput));
+        assert_eq!(restored.drain_output().collect::<Vec<_>>(), [15, 21, 27]);
+
+        let truncated = Machine::<DenseMemory>::load_snapshot(&snapshot[..snapshot.len() - 1]);
+        assert_eq!(truncated.err().map(|e| e.kind()), Some(io::ErrorKind::InvalidData));
+        let invalid = Machine::<DenseMemory>::load_snapshot(&b"ICTR\x01"[..]);
+        assert_eq!(invalid.err().map(|e| e.kind()), Some(io::ErrorKind::InvalidData));
+
+        // the arithmetic policy is restored: [0] = i64::MAX + 1 wraps instead of faulting
+        let mut machine = Machine::new(&[1101, i64::MAX, 1, 0, 99]);
+        machine.set_arithmetic(Arithmetic::Wrapping);
+        let mut snapshot = Vec::new();
+        machine.save_snapshot(&mut snapshot).unwrap();
+        let mut restored: Machine = Machine::load_snapshot(snapshot.as_slice()).unwrap();
+        assert_eq!(restored.arithmetic(), Arithmetic::Wrapping);
+        assert_eq!(restored.run(), Ok(State::Halt));
+        assert_eq!(restored.peek(0), i64::MIN);
+    }
+
+    #[cfg(feature = "bignum")]
+    #[test]
+    fn test_snapshot_big_values() {
+        use num_bigint::BigInt;
+
+        // [0] = [9] * [10], then [1] = [0] == [9]
+        let mut machine = Machine::new(&[2, 9, 10, 0, 8, 0, 9, 1, 99, 0, 0]);
+        machine.set_arithmetic(Arithmetic::Big);
+        machine.poke_big(9, BigInt::from(2).pow(64));
+        machine.poke_big(10, -BigInt::from(2).pow(64));
+        let mut snapshot = Vec::new();
+        machine.save_snapshot(&mut snapshot).unwrap();
+
+        let mut restored: Machine = Machine::load_snapshot(snapshot.as_slice()).unwrap();
+        assert_eq!(restored.arithmetic(), Arithmetic::Big);
+        assert_eq!(restored.big_cells().collect::<Vec<_>>(), machine.big_cells().collect::<Vec<_>>());
+        assert_eq!(restored.run(), Ok(State::Halt));
+        assert_eq!(restored.peek_big(0), -BigInt::from(2).pow(128));
+        assert_eq!(restored.peek(1), 0);
+
+        let truncated = Machine::<DenseMemory>::load_snapshot(&snapshot[..snapshot.len() - 5]);
+        assert_eq!(truncated.err().map(|e| e.kind()), Some(io::ErrorKind::InvalidData));
+    }
+}
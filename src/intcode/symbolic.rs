@@ -0,0 +1,318 @@
+//! Symbolic execution: run a program with some memory cells as variables,
+//! building expressions of the variables instead of computing values, to
+//! find the variable values that produce a given result without trying all
+//! of them.
+//!
+//! Only straight-line arithmetic can be followed symbolically: a branch, a
+//! comparison or a write that depends on a variable, or any I/O, stops the
+//! execution with a `SymError`. Reading from an address that depends on a
+//! variable is allowed, but the value read is unknown (`Expr::Load`).
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+use itertools::Itertools;
+use super::{Instr, Machine, Mode, Opcode};
+
+/// Give up if the program runs longer than this
+const MAX_STEPS: u64 = 1_000_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Const(i64),
+    /// The Nth variable
+    Var(usize),
+    /// Value at an address that depends on the variables
+    Load(Rc<Expr>),
+    Add(Rc<Expr>, Rc<Expr>),
+    Mul(Rc<Expr>, Rc<Expr>),
+}
+
+/// Why the symbolic execution stopped before the program halted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SymError {
+    /// The instruction at `ip` can't be executed symbolically
+    Unsupported { ip: i64, reason: &'static str },
+    /// Invalid instruction, or an overflow or negative address with constants
+    Fault { ip: i64 },
+    TooLong,
+}
+
+/// A polynomial of the variables: coefficient of every monomial, which is
+/// the sorted list of the variables multiplied in it
+type Poly = BTreeMap<Vec<usize>, i64>;
+
+impl Expr {
+    fn add(lhs: Rc<Expr>, rhs: Rc<Expr>) -> Option<Rc<Expr>> {
+        Some(match (&*lhs, &*rhs) {
+            (Expr::Const(a), Expr::Const(b)) => Rc::new(Expr::Const(a.checked_add(*b)?)),
+            (Expr::Const(0), _) => rhs,
+            (_, Expr::Const(0)) => lhs,
+            _ => Rc::new(Expr::Add(lhs, rhs)),
+        })
+    }
+
+    fn mul(lhs: Rc<Expr>, rhs: Rc<Expr>) -> Option<Rc<Expr>> {
+        Some(match (&*lhs, &*rhs) {
+            (Expr::Const(a), Expr::Const(b)) => Rc::new(Expr::Const(a.checked_mul(*b)?)),
+            (Expr::Const(0), _) | (_, Expr::Const(0)) => Rc::new(Expr::Const(0)),
+            (Expr::Const(1), _) => rhs,
+            (_, Expr::Const(1)) => lhs,
+            _ => Rc::new(Expr::Mul(lhs, rhs)),
+        })
+    }
+
+    pub fn as_const(&self) -> Option<i64> {
+        match self {
+            Expr::Const(val) => Some(*val),
+            _ => None,
+        }
+    }
+
+    /// Expand the expression into a polynomial, if it has no unknown loads
+    /// and its coefficients don't overflow
+    fn to_poly(&self) -> Option<Poly> {
+        match self {
+            Expr::Const(val) => Some(Poly::from([(vec![], *val)])),
+            Expr::Var(var) => Some(Poly::from([(vec![*var], 1)])),
+            Expr::Load(_) => None,
+            Expr::Add(lhs, rhs) => {
+                let mut poly = lhs.to_poly()?;
+                for (mono, coef) in rhs.to_poly()? {
+                    let sum = poly.entry(mono).or_insert(0);
+                    *sum = sum.checked_add(coef)?;
+                }
+                Some(poly)
+            },
+            Expr::Mul(lhs, rhs) => {
+                let (lhs, rhs) = (lhs.to_poly()?, rhs.to_poly()?);
+                let mut poly = Poly::new();
+                for ((mono_l, coef_l), (mono_r, coef_r)) in lhs.iter().cartesian_product(&rhs) {
+                    let mono = mono_l.iter().chain(mono_r).copied().sorted().collect();
+                    let sum = poly.entry(mono).or_insert(0);
+                    *sum = sum.checked_add(coef_l.checked_mul(*coef_r)?)?;
+                }
+                Some(poly)
+            },
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Const(val) => write!(f, "{}", val),
+            Expr::Var(var) => write!(f, "v{}", var),
+            Expr::Load(addr) => write!(f, "[{}]", addr),
+            Expr::Add(lhs, rhs) => write!(f, "({} + {})", lhs, rhs),
+            Expr::Mul(lhs, rhs) => write!(f, "{} * {}", lhs, rhs),
+        }
+    }
+}
+
+/// Memory after running a program symbolically
+pub struct SymMemory {
+    cells: HashMap<usize, Rc<Expr>>,
+}
+
+impl SymMemory {
+    pub fn get(&self, addr: usize) -> Rc<Expr> {
+        self.cells.get(&addr).cloned().unwrap_or_else(|| Rc::new(Expr::Const(0)))
+    }
+}
+
+/// Run `prog` until it halts, with the cells at `vars` as the variables
+/// `v0`, `v1`...
+pub fn execute(prog: &[i64], vars: &[usize]) -> Result<SymMemory, SymError> {
+    let mut mem = SymMemory {
+        cells: prog.iter().enumerate().map(|(addr, &val)| (addr, Rc::new(Expr::Const(val)))).collect(),
+    };
+    for (i, &addr) in vars.iter().enumerate() {
+        mem.cells.insert(addr, Rc::new(Expr::Var(i)));
+    }
+
+    let (mut ip, mut rel_base) = (0_i64, 0_i64);
+    for _ in 0..MAX_STEPS {
+        let unsupported = |reason| SymError::Unsupported { ip, reason };
+        let fault = SymError::Fault { ip };
+
+        let word = mem.get(ip as usize).as_const().ok_or(unsupported("instruction depends on variables"))?;
+        let instr = Instr::decode(ip as usize, |addr| match addr == ip as usize {
+            true => word,
+            false => mem.get(addr).as_const().unwrap_or(0),
+        }).map_err(|_| fault.clone())?;
+
+        // operand values, and their addresses if they are constant
+        let mut operands = Vec::new();
+        for (i, param) in instr.params().iter().enumerate() {
+            let value = mem.get(ip as usize + 1 + i);
+            let addr = match (param.mode, value.as_const()) {
+                (Mode::Imm, _) => None,
+                (Mode::Pos, Some(addr)) => Some(addr),
+                (Mode::Rel, Some(offset)) => Some(rel_base.checked_add(offset).ok_or(fault.clone())?),
+                (Mode::Pos, None) => {
+                    operands.push((Rc::new(Expr::Load(value)), None));
+                    continue;
+                },
+                (Mode::Rel, None) => {
+                    let addr = Expr::add(Rc::new(Expr::Const(rel_base)), value).ok_or(fault.clone())?;
+                    operands.push((Rc::new(Expr::Load(addr)), None));
+                    continue;
+                },
+            };
+            let addr = addr.map(usize::try_from).transpose().map_err(|_| fault.clone())?;
+            operands.push((addr.map_or(value, |addr| mem.get(addr)), addr));
+        }
+        let dest = |i: usize| match (instr.params()[i].mode, operands[i].1) {
+            (Mode::Imm, _) => Err(fault.clone()),
+            (_, Some(addr)) => Ok(addr),
+            (_, None) => Err(unsupported("write to an address that depends on variables")),
+        };
+        let concrete = |i: usize| operands[i].0.as_const().ok_or(unsupported("comparison or branch depends on variables"));
+
+        let mut next_ip = ip + instr.size() as i64;
+        match instr.opcode {
+            Opcode::Add | Opcode::Mul => {
+                let (lhs, rhs) = (operands[0].0.clone(), operands[1].0.clone());
+                let val = match instr.opcode {
+                    Opcode::Add => Expr::add(lhs, rhs),
+                    _ => Expr::mul(lhs, rhs),
+                };
+                mem.cells.insert(dest(2)?, val.ok_or(fault.clone())?);
+            },
+            Opcode::Lt | Opcode::Eq => {
+                let (lhs, rhs) = (concrete(0)?, concrete(1)?);
+                let val = match instr.opcode {
+                    Opcode::Lt => lhs < rhs,
+                    _ => lhs == rhs,
+                };
+                mem.cells.insert(dest(2)?, Rc::new(Expr::Const(val as i64)));
+            },
+            Opcode::Jt | Opcode::Jf => {
+                if (concrete(0)? != 0) == (instr.opcode == Opcode::Jt) {
+                    next_ip = concrete(1)?;
+                }
+            },
+            Opcode::Arb => rel_base = rel_base.checked_add(concrete(0)?).ok_or(fault.clone())?,
+            Opcode::In | Opcode::Out => return Err(unsupported("I/O")),
+            Opcode::Hlt => return Ok(mem),
+        }
+        ip = next_ip;
+    }
+    Err(SymError::TooLong)
+}
+
+/// Find values for the cells at `vars`, all of them in `domain`, that make
+/// the program halt with `target` at address `result`.
+///
+/// If the result is a linear expression of the variables, it's solved
+/// directly for one of them. Otherwise, all the combinations are tried: by
+/// evaluating the expression if it's known, or by running the program if the
+/// symbolic execution failed.
+pub fn solve(prog: &[i64], vars: &[usize], domain: RangeInclusive<i64>, result: usize, target: i64)
+        -> Option<Vec<i64>> {
+    if domain.is_empty() {
+        return None;
+    }
+    let poly = execute(prog, vars).ok().and_then(|mem| mem.get(result).to_poly());
+    let combinations = |n| (0..n).map(|_| domain.clone()).multi_cartesian_product();
+
+    match poly {
+        Some(poly) if poly.keys().all(|mono| mono.len() <= 1) => {
+            // c + a0 * v0 + a1 * v1... = target, solve for the last variable
+            // with a coefficient, trying all the values of the others
+            let coef = |var| poly.get(&vec![var]).copied().unwrap_or(0);
+            let constant = poly.get(&vec![]).copied().unwrap_or(0);
+            let Some(solved) = (0..vars.len()).rev().find(|&var| coef(var) != 0) else {
+                return (constant == target).then(|| vec![*domain.start(); vars.len()]);
+            };
+
+            let others = (0..vars.len()).filter(|&var| var != solved).collect_vec();
+            let others_vals = if others.is_empty() { vec![vec![]] } else { combinations(others.len()).collect() };
+            others_vals.into_iter().find_map(|vals| {
+                let mut rest = (target as i128) - (constant as i128);
+                for (&var, &val) in others.iter().zip(&vals) {
+                    rest -= coef(var) as i128 * val as i128;
+                }
+                let a = coef(solved) as i128;
+                let solved_val = i64::try_from(rest / a).ok().filter(|v| rest % a == 0 && domain.contains(v))?;
+
+                let mut solution = vec![0; vars.len()];
+                for (&var, &val) in others.iter().zip(&vals) {
+                    solution[var] = val;
+                }
+                solution[solved] = solved_val;
+                Some(solution)
+            })
+        },
+        Some(poly) => combinations(vars.len()).find(|vals| eval_poly(&poly, vals) == Some(target)),
+        None => {
+            let machine = Machine::new(prog);
+            combinations(vars.len()).find(|vals| run_concrete(&machine, vars, vals, result) == Some(target))
+        },
+    }
+}
+
+fn eval_poly(poly: &Poly, vals: &[i64]) -> Option<i64> {
+    poly.iter().try_fold(0_i64, |sum, (mono, &coef)| {
+        let term = mono.iter().try_fold(coef, |acc, &var| acc.checked_mul(vals[var]))?;
+        sum.checked_add(term)
+    })
+}
+
+/// Run a fork of `machine`, so the program is not loaded again for every
+/// combination of values
+fn run_concrete(machine: &Machine, vars: &[usize], vals: &[i64], result: usize) -> Option<i64> {
+    let mut machine = machine.fork();
+    for (&addr, &val) in vars.iter().zip(vals) {
+        machine.poke(addr, val);
+    }
+    machine.run_to_halt().ok()?;
+    Some(machine.peek(result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_execute() {
+        // [0] = ([9] + [10]) * [10]
+        let mem = execute(&[1, 9, 10, 0, 2, 0, 10, 0, 99, 0, 0], &[9, 10]).unwrap();
+        assert_eq!(mem.get(0).to_string(), "(v0 + v1) * v1");
+
+        // reading from a variable address gives an unknown value
+        let mem = execute(&[1, 5, 6, 0, 99, 0, 0], &[1]).unwrap();
+        assert_eq!(mem.get(0).to_string(), "[v0]");
+        assert_eq!(mem.get(0).to_poly(), None);
+
+        // also relative to rb: [0] = [rb + [3]]
+        let mem = execute(&[109, 5, 201, 0, 9, 0, 99, 0, 0, 0], &[3]).unwrap();
+        assert_eq!(mem.get(0).to_string(), "[(5 + v0)]");
+
+        assert_eq!(execute(&[1005, 3, 0, 99], &[3]).err(),
+                   Some(SymError::Unsupported { ip: 0, reason: "comparison or branch depends on variables" }));
+    }
+
+    #[test]
+    fn test_solve() {
+        // [0] = 3 * [9] + [10]
+        let prog = [1002, 9, 3, 0, 1, 0, 10, 0, 99, 0, 0];
+        assert_eq!(solve(&prog, &[9, 10], 0..=99, 0, 305), Some(vec![69, 98]));
+        assert_eq!(solve(&prog, &[9, 10], 0..=9, 0, 305), None);
+
+        // nonlinear: [0] = [9] * [10]
+        let prog = [2, 9, 10, 0, 99, 0, 0, 0, 0, 0, 0];
+        assert_eq!(solve(&prog, &[9, 10], 0..=99, 0, 91), Some(vec![1, 91]));
+
+        // not symbolic: [0] = [[1]] + [10]
+        let prog = [1, 0, 10, 0, 99, 0, 0, 0, 0, 0, 0, 0, 42];
+        assert_eq!(solve(&prog, &[1], 0..=20, 0, 42), Some(vec![12]));
+
+        // constant: [0] = 2, whatever [5] is
+        let prog = [1101, 1, 1, 0, 99, 0];
+        assert_eq!(solve(&prog, &[5], 3..=9, 0, 2), Some(vec![3]));
+        assert_eq!(solve(&prog, &[5], RangeInclusive::new(5, 4), 0, 2), None);
+    }
+}
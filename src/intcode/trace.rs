@@ -0,0 +1,260 @@
+//! Execution tracing: a `Tracer` set with `Machine::set_tracer` receives a
+//! `TraceRecord` for every executed instruction.
+//!
+//! Records can be written as human readable text with `TextTracer`, or to a
+//! compact binary log with `BinaryTracer` that can be read back with
+//! `TraceReader`, i.e. to compare two executions with the `tracediff` tool.
+
+use std::any::Any;
+use std::fmt;
+use std::io::{self, Read, Write};
+use super::{Instr, Mode};
+use super::varint::{self, invalid_data};
+
+/// Magic bytes and version at the start of binary trace logs
+const BINARY_MAGIC: &[u8; 4] = b"ICTR";
+const BINARY_VERSION: u8 = 1;
+
+pub trait Tracer: Any + Send {
+    fn trace(&mut self, record: &TraceRecord);
+}
+
+/// An executed instruction, with the machine registers before executing it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceRecord {
+    /// Number of instructions executed before this one
+    pub count: u64,
+    pub ip: i64,
+    pub rel_base: i64,
+    pub instr: Instr,
+    operands: [TraceOperand; 3],
+}
+
+/// Value of an instruction operand before and after executing it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TraceOperand {
+    /// Address that the operand refers to, `None` for immediate operands or
+    /// if it's an invalid address
+    pub addr: Option<usize>,
+    pub pre: i64,
+    pub post: i64,
+}
+
+impl TraceRecord {
+    pub fn new(count: u64, ip: i64, rel_base: i64, instr: Instr, operands: &[TraceOperand]) -> TraceRecord {
+        let mut record = TraceRecord { count, ip, rel_base, instr, operands: Default::default() };
+        record.operands[..operands.len()].copy_from_slice(operands);
+        record
+    }
+
+    pub fn operands(&self) -> &[TraceOperand] {
+        &self.operands[..self.instr.params().len()]
+    }
+}
+
+impl fmt::Display for TraceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let instr = self.instr.to_string();
+        write!(f, "{:>8} {:>6} rb={:<6} {:<40}", self.count, self.ip, self.rel_base, instr)?;
+        for (param, operand) in self.instr.params().iter().zip(self.operands()) {
+            match operand.addr {
+                _ if param.mode == Mode::Imm => write!(f, " #{}", operand.pre)?,
+                None => write!(f, " @?")?,
+                Some(addr) if operand.pre == operand.post => write!(f, " @{}={}", addr, operand.pre)?,
+                Some(addr) => write!(f, " @{}={}->{}", addr, operand.pre, operand.post)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Collect the records in memory
+impl Tracer for Vec<TraceRecord> {
+    fn trace(&mut self, record: &TraceRecord) {
+        self.push(*record);
+    }
+}
+
+/// Write one line of text per record. Write errors are deferred until
+/// calling `into_inner`, after the first one nothing else is written.
+pub struct TextTracer<W> {
+    writer: W,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> TextTracer<W> {
+    pub fn new(writer: W) -> TextTracer<W> {
+        TextTracer { writer, error: None }
+    }
+
+    /// Flush and return the writer, or the first error found while writing
+    pub fn into_inner(mut self) -> io::Result<W> {
+        match self.error.take() {
+            Some(err) => Err(err),
+            None => self.writer.flush().map(|_| self.writer),
+        }
+    }
+}
+
+impl<W: Write + Send + 'static> Tracer for TextTracer<W> {
+    fn trace(&mut self, record: &TraceRecord) {
+        if self.error.is_none() {
+            self.error = writeln!(self.writer, "{}", record).err();
+        }
+    }
+}
+
+/// Write the records in a compact binary format, with all numbers encoded as
+/// zigzag LEB128 varints. Errors are deferred like in `TextTracer`.
+pub struct BinaryTracer<W> {
+    writer: W,
+    error: Option<io::Error>,
+    last_count: u64,
+}
+
+impl<W: Write> BinaryTracer<W> {
+    pub fn new(mut writer: W) -> BinaryTracer<W> {
+        let error = writer.write_all(BINARY_MAGIC)
+            .and_then(|_| writer.write_all(&[BINARY_VERSION]))
+            .err();
+        BinaryTracer { writer, error, last_count: 0 }
+    }
+
+    /// Flush and return the writer, or the first error found while writing
+    pub fn into_inner(mut self) -> io::Result<W> {
+        match self.error.take() {
+            Some(err) => Err(err),
+            None => self.writer.flush().map(|_| self.writer),
+        }
+    }
+
+    fn write_record(&mut self, record: &TraceRecord) -> io::Result<()> {
+        let mut buf = Vec::with_capacity(32);
+        // counts are stored as the delta with the previous record, usually 1
+        varint::write(&mut buf, record.count.wrapping_sub(self.last_count) as i64);
+        varint::write(&mut buf, record.ip);
+        varint::write(&mut buf, record.rel_base);
+        varint::write(&mut buf, record.instr.word);
+        for (param, operand) in record.instr.params().iter().zip(record.operands()) {
+            varint::write(&mut buf, param.value);
+            varint::write(&mut buf, operand.addr.map_or(-1, |addr| addr as i64));
+            varint::write(&mut buf, operand.pre);
+            varint::write(&mut buf, operand.post);
+        }
+        self.last_count = record.count;
+        self.writer.write_all(&buf)
+    }
+}
+
+impl<W: Write + Send + 'static> Tracer for BinaryTracer<W> {
+    fn trace(&mut self, record: &TraceRecord) {
+        if self.error.is_none() {
+            self.error = self.write_record(record).err();
+        }
+    }
+}
+
+/// Iterator over the records of a binary trace log
+pub struct TraceReader<R> {
+    reader: R,
+    last_count: u64,
+}
+
+impl<R: Read> TraceReader<R> {
+    /// Check the header of the log and return a reader for its records
+    pub fn new(mut reader: R) -> io::Result<TraceReader<R>> {
+        let mut header = [0; 5];
+        reader.read_exact(&mut header)?;
+        if &header[..4] != BINARY_MAGIC || header[4] != BINARY_VERSION {
+            return Err(invalid_data("not an Intcode binary trace, or unsupported version"));
+        }
+        Ok(TraceReader { reader, last_count: 0 })
+    }
+
+    fn read_record(&mut self) -> io::Result<Option<TraceRecord>> {
+        let count_delta = match varint::read(&mut self.reader) {
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            res => res?,
+        };
+        let count = self.last_count.wrapping_add(count_delta as u64);
+        let ip = varint::read_more(&mut self.reader)?;
+        let rel_base = varint::read_more(&mut self.reader)?;
+
+        let mut cells = [varint::read_more(&mut self.reader)?, 0, 0, 0];
+        let n_params = super::Opcode::from_code(cells[0] % 100)
+            .ok_or_else(|| invalid_data("invalid instruction"))?
+            .param_count();
+        let mut operands = [TraceOperand::default(); 3];
+        for (i, operand) in operands.iter_mut().enumerate().take(n_params) {
+            cells[i + 1] = varint::read_more(&mut self.reader)?;
+            let addr = varint::read_more(&mut self.reader)?;
+            operand.addr = usize::try_from(addr).ok();
+            operand.pre = varint::read_more(&mut self.reader)?;
+            operand.post = varint::read_more(&mut self.reader)?;
+        }
+        let instr = Instr::decode(0, |addr| cells[addr])
+            .map_err(|_| invalid_data("invalid instruction"))?;
+
+        self.last_count = count;
+        Ok(Some(TraceRecord::new(count, ip, rel_base, instr, &operands[..n_params])))
+    }
+}
+
+impl<R: Read> Iterator for TraceReader<R> {
+    type Item = io::Result<TraceRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::Machine;
+
+    // [9] = in; [10] = [9] * 2; out [10]
+    const PROG: [i64; 11] = [3, 9, 1002, 9, 2, 10, 4, 10, 99, 0, 0];
+
+    fn run_traced(tracer: impl Tracer) -> Machine {
+        let mut machine = Machine::new(&PROG);
+        machine.set_tracer(tracer);
+        machine.push_input(21);
+        machine.run().unwrap();
+        machine
+    }
+
+    #[test]
+    fn test_trace_records() {
+        let records = run_traced(Vec::new()).take_tracer::<Vec<TraceRecord>>().unwrap();
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[1].ip, 2);
+        assert_eq!(records[1].operands()[0], TraceOperand { addr: Some(9), pre: 21, post: 21 });
+        assert_eq!(records[1].operands()[2], TraceOperand { addr: Some(10), pre: 0, post: 42 });
+        assert_eq!(records[1].to_string(),
+                   "       1      2 rb=0      MUL [9], #2, [10]                        @9=21 #2 @10=0->42");
+    }
+
+    #[test]
+    fn test_take_tracer_wrong_type() {
+        let mut machine = run_traced(Vec::new());
+        assert!(machine.take_tracer::<TextTracer<Vec<u8>>>().is_none());
+        assert_eq!(machine.take_tracer::<Vec<TraceRecord>>().map(|r| r.len()), Some(4));
+        assert!(machine.take_tracer::<Vec<TraceRecord>>().is_none());
+    }
+
+    #[test]
+    fn test_binary_roundtrip() {
+        let records = run_traced(Vec::new()).take_tracer::<Vec<TraceRecord>>().unwrap();
+        let log = run_traced(BinaryTracer::new(Vec::new()))
+            .take_tracer::<BinaryTracer<Vec<u8>>>().unwrap()
+            .into_inner().unwrap();
+
+        let read: Vec<TraceRecord> = TraceReader::new(log.as_slice()).unwrap()
+            .collect::<io::Result<_>>().unwrap();
+        assert_eq!(read, records);
+
+        let truncated = TraceReader::new(&log[..log.len() - 1]).unwrap().last().unwrap();
+        assert_eq!(truncated.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
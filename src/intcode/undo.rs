@@ -0,0 +1,160 @@
+//! Reverse execution: `UndoLog` keeps the trace records of the last executed
+//! instructions, which have everything needed to revert them with
+//! `Machine::undo`: the registers before the instruction, and the value that
+//! its destination had before being written.
+
+use std::collections::VecDeque;
+use super::{Machine, Memory, Opcode};
+use super::trace::{TraceRecord, Tracer};
+
+/// Tracer that keeps the records of the last `capacity` executed
+/// instructions, dropping the oldest ones
+#[derive(Debug, Clone)]
+pub struct UndoLog {
+    records: VecDeque<TraceRecord>,
+    capacity: usize,
+}
+
+impl UndoLog {
+    pub fn new(capacity: usize) -> UndoLog {
+        UndoLog { records: VecDeque::new(), capacity }
+    }
+
+    /// Remove the record of the last executed instruction, to pass it to
+    /// `Machine::undo`
+    pub fn pop(&mut self) -> Option<TraceRecord> {
+        self.records.pop_back()
+    }
+
+    /// Record of the last executed instruction
+    pub fn last(&self) -> Option<&TraceRecord> {
+        self.records.back()
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Forget all the records, i.e. after changing the machine state by hand,
+    /// which couldn't be undone
+    pub fn clear(&mut self) {
+        self.records.clear();
+    }
+}
+
+impl Tracer for UndoLog {
+    fn trace(&mut self, record: &TraceRecord) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.records.len() == self.capacity {
+            self.records.pop_front();
+        }
+        self.records.push_back(*record);
+    }
+}
+
+impl<M: Memory> Machine<M> {
+    /// Revert the instruction of `record`, which must be the last one that
+    /// the machine executed: restore the written cell, the registers and the
+    /// instruction count, put a read value back at the front of the input
+    /// queue and remove a written value from the back of the output queue.
+    /// Output is consumed from the front, so the written value is the last one
+    /// left, unless the queue has been emptied since then. A value that didn't fit in an i64 (see
+    /// `Arithmetic::Big`) is not in the record, so it's restored saturated.
+    pub fn undo(&mut self, record: &TraceRecord) {
+        debug_assert_eq!(record.count + 1, self.instr_count, "undo of an instruction that is not the last one");
+
+        if let Some(param) = record.instr.opcode.dest_param() {
+            let operand = record.operands()[param];
+            if let Some(addr) = operand.addr {
+                self.poke(addr, operand.pre);
+            }
+        }
+        match record.instr.opcode {
+            Opcode::In => self.input.push_front(record.operands()[0].post),
+            Opcode::Out => {
+                self.output.pop_back();
+            },
+            Opcode::Hlt => self.halted = false,
+            _ => (),
+        }
+        self.ip = record.ip;
+        self.rel_base = record.rel_base;
+        self.instr_count = record.count;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::State;
+
+    /// Everything that `undo` restores
+    fn state(machine: &Machine) -> (i64, i64, u64, bool, Vec<i64>, Vec<i64>, Vec<i64>) {
+        let mem = (0..128).map(|addr| machine.peek(addr)).collect();
+        let input = machine.input().iter().copied().collect();
+        let output = machine.output().iter().copied().collect();
+        (machine.ip(), machine.rel_base(), machine.instr_count(), machine.is_halted(), mem, input, output)
+    }
+
+    #[test]
+    fn test_undo() {
+        let progs: [&[i64]; 2] = [
+            // out in * 3, until in is 0
+            &[3, 15, 1006, 15, 14, 1002, 15, 3, 15, 4, 15, 1105, 1, 0, 99, 0],
+            // quine, writing beyond the image through rb
+            &[109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99],
+        ];
+        for prog in progs {
+            let mut machine = Machine::new(prog);
+            machine.extend_input([4, 5, 0]);
+            machine.set_tracer(UndoLog::new(1000));
+
+            let mut states = vec![state(&machine)];
+            while !machine.is_halted() {
+                machine.step().unwrap();
+                states.push(state(&machine));
+            }
+            states.pop();
+
+            while let Some(record) = machine.tracer_mut::<UndoLog>().unwrap().pop() {
+                machine.undo(&record);
+                assert_eq!(state(&machine), states.pop().unwrap());
+            }
+            assert!(states.is_empty());
+
+            // runs again the same way
+            assert_eq!(machine.run(), Ok(State::Halt));
+        }
+
+        // only the last instructions are kept
+        let mut machine = Machine::new(progs[1]);
+        machine.set_tracer(UndoLog::new(2));
+        assert_eq!(machine.run(), Ok(State::Halt));
+        let count = machine.instr_count();
+        let log = machine.tracer_mut::<UndoLog>().unwrap();
+        assert_eq!(log.len(), 2);
+        assert_eq!(log.last().map(|record| (record.count, record.instr.opcode)), Some((count - 1, Opcode::Hlt)));
+
+        // consumed output is not removed again, even if an equal value is left
+        let mut machine = Machine::new(&[104, 7, 104, 7, 104, 8, 99]);
+        machine.set_tracer(UndoLog::new(10));
+        assert_eq!(machine.run(), Ok(State::Halt));
+        assert_eq!(machine.pop_output(), Some(7));
+        let undo = |machine: &mut Machine| {
+            let record = machine.tracer_mut::<UndoLog>().unwrap().pop().unwrap();
+            machine.undo(&record);
+            machine.output().iter().copied().collect::<Vec<_>>()
+        };
+        assert_eq!(undo(&mut machine), [7, 8]);
+        assert_eq!(undo(&mut machine), [7]);
+        assert_eq!(undo(&mut machine), []);
+        assert_eq!(undo(&mut machine), []);
+        assert_eq!(machine.ip(), 0);
+    }
+}
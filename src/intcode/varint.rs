@@ -0,0 +1,64 @@
+//! Zigzag LEB128 varints, used by the binary trace and snapshot formats
+
+use std::io::{self, Read};
+
+pub fn write(buf: &mut Vec<u8>, val: i64) {
+    let mut zigzag = ((val << 1) ^ (val >> 63)) as u64;
+    loop {
+        let byte = (zigzag & 0x7f) as u8;
+        zigzag >>= 7;
+        if zigzag == 0 {
+            buf.push(byte);
+            return;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+/// Read a varint. EOF before the first byte is returned as is, so the caller
+/// can tell a clean end of data, but EOF in the middle of a varint means
+/// truncated data and is returned as `InvalidData`.
+pub fn read(reader: &mut impl Read) -> io::Result<i64> {
+    let mut zigzag: u64 = 0;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0];
+        if let Err(err) = reader.read_exact(&mut byte) {
+            return match (shift, err.kind()) {
+                (0, _) => Err(err),
+                (_, io::ErrorKind::UnexpectedEof) => Err(invalid_data("truncated data")),
+                _ => Err(err),
+            };
+        }
+        zigzag |= ((byte[0] & 0x7f) as u64) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok((zigzag >> 1) as i64 ^ -((zigzag & 1) as i64));
+        }
+    }
+    Err(invalid_data("varint too long"))
+}
+
+/// Read a varint that must be present, so any EOF means truncated data
+pub fn read_more(reader: &mut impl Read) -> io::Result<i64> {
+    read(reader).map_err(|err| match err.kind() {
+        io::ErrorKind::UnexpectedEof => invalid_data("truncated data"),
+        _ => err,
+    })
+}
+
+pub fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_varint() {
+        for val in [0, 1, -1, 63, -64, 64, 1 << 40, i64::MAX, i64::MIN] {
+            let mut buf = Vec::new();
+            write(&mut buf, val);
+            assert_eq!(read(&mut buf.as_slice()).unwrap(), val);
+        }
+    }
+}